@@ -0,0 +1,259 @@
+// `.frzrignore` files, and the --exclude/--include overrides for them. Patterns follow gitignore
+// semantics: globs, `!` to negate, a trailing `/` for directories only, and a leading `/` (or
+// any `/` but a trailing one) to anchor a pattern to the directory its .frzrignore is in
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+pub const IGNORE_FILE_NAME: &str = ".frzrignore";
+
+// Where the rules given with --exclude and --include are recorded as coming from
+pub const COMMAND_LINE_SOURCE: &str = "command line";
+
+// One pattern, and the .frzrignore (or the command line) it came from. Each run records every
+// rule that was in effect, so that a report can explain why a file stopped being checked
+#[derive(Clone)]
+pub struct IgnoreRule {
+    pub source: String,
+    pub pattern: String,
+}
+
+// The matchers that apply at the current point of a walk, plus every rule seen so far
+pub struct IgnoreStack {
+    // The command line wins over any .frzrignore
+    command_line: Gitignore,
+    // (directory, matcher) for each .frzrignore between the root and the current directory,
+    // outermost first
+    files: Vec<(PathBuf, Gitignore)>,
+    pub rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreStack {
+    // Patterns are relative to the root of the walk. Includes beat excludes
+    pub fn new(excludes: &[String], includes: &[String]) -> Result<IgnoreStack, io::Error> {
+        let mut rules: Vec<IgnoreRule> = Vec::new();
+        for exclude in excludes {
+            rules.push(IgnoreRule {
+                source: COMMAND_LINE_SOURCE.to_string(),
+                pattern: exclude.clone(),
+            });
+        }
+        for include in includes {
+            rules.push(IgnoreRule {
+                source: COMMAND_LINE_SOURCE.to_string(),
+                pattern: format!("!{}", include.trim_start_matches('!')),
+            });
+        }
+        let command_line = build_matcher(Path::new("."), None, &rules)?;
+        Ok(IgnoreStack {
+            command_line,
+            files: Vec::new(),
+            rules,
+        })
+    }
+
+    // Rebuilds the matchers from rules recorded on a run, for explaining why paths were ignored.
+    // Patterns that don't parse are skipped; they can't have matched anything either
+    pub fn from_rules(rules: &[IgnoreRule]) -> IgnoreStack {
+        let mut sources: Vec<&str> = Vec::new();
+        for rule in rules {
+            if !sources.contains(&rule.source.as_str()) {
+                sources.push(&rule.source);
+            }
+        }
+        let mut stack = IgnoreStack {
+            command_line: Gitignore::empty(),
+            files: Vec::new(),
+            rules: rules.to_vec(),
+        };
+        for source in sources {
+            let source_rules: Vec<IgnoreRule> = rules
+                .iter()
+                .filter(|rule| rule.source == source)
+                .cloned()
+                .collect();
+            if source == COMMAND_LINE_SOURCE {
+                stack.command_line = build_lenient_matcher(Path::new("."), None, &source_rules);
+            } else {
+                let source_path = PathBuf::from(source);
+                let dir = source_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+                let matcher = build_lenient_matcher(&dir, Some(&source_path), &source_rules);
+                stack.files.push((dir, matcher));
+            }
+        }
+        // Outermost first, like during a walk
+        stack.files.sort_by_key(|(dir, _)| dir.components().count());
+        stack
+    }
+
+    // Starts applying dir's .frzrignore, if it has one. Returns whether it did, in which case
+    // `leave` has to be called once the walk is done with dir
+    pub fn enter(&mut self, dir: &Path) -> Result<bool, io::Error> {
+        let ignore_file = dir.join(IGNORE_FILE_NAME);
+        let contents = match std::fs::read(&ignore_file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let source = ignore_file.to_string_lossy().to_string();
+        let rules: Vec<IgnoreRule> = String::from_utf8_lossy(&contents)
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| IgnoreRule {
+                source: source.clone(),
+                pattern: line.to_string(),
+            })
+            .collect();
+        let matcher = build_matcher(dir, Some(&ignore_file), &rules)?;
+        self.rules.extend(rules);
+        self.files.push((dir.to_path_buf(), matcher));
+        Ok(true)
+    }
+
+    pub fn leave(&mut self) {
+        self.files.pop();
+    }
+
+    // Whether the walk should skip path. The command line is consulted first, then the
+    // .frzrignore files from the innermost out; the first one with an opinion decides
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if let Some(ignored) = decide(self.command_line.matched(path, is_dir)) {
+            return ignored;
+        }
+        for (_, matcher) in self.files.iter().rev() {
+            if let Some(ignored) = decide(matcher.matched(path, is_dir)) {
+                return ignored;
+            }
+        }
+        false
+    }
+
+    // The rule that ignores the file at path (or one of the directories it is in), if any
+    pub fn explain(&self, path: &Path) -> Option<IgnoreRule> {
+        let applicable = self
+            .files
+            .iter()
+            .rev()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .map(|(_, matcher)| matcher);
+        for matcher in std::iter::once(&self.command_line).chain(applicable) {
+            match matcher.matched_path_or_any_parents(path, false) {
+                Match::None => continue,
+                Match::Whitelist(_) => return None,
+                Match::Ignore(glob) => {
+                    let source = match glob.from() {
+                        Some(from) => from.to_string_lossy().to_string(),
+                        None => COMMAND_LINE_SOURCE.to_string(),
+                    };
+                    return Some(IgnoreRule {
+                        source,
+                        pattern: glob.original().to_string(),
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+fn decide<T>(matched: Match<T>) -> Option<bool> {
+    match matched {
+        Match::None => None,
+        Match::Ignore(_) => Some(true),
+        Match::Whitelist(_) => Some(false),
+    }
+}
+
+fn build_matcher(
+    dir: &Path,
+    source: Option<&Path>,
+    rules: &[IgnoreRule],
+) -> Result<Gitignore, io::Error> {
+    let mut builder = GitignoreBuilder::new(dir);
+    for rule in rules {
+        if let Err(e) = builder.add_line(source.map(Path::to_path_buf), &rule.pattern) {
+            let message = format!("bad pattern in {}: {}", rule.source, e);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+fn build_lenient_matcher(dir: &Path, source: Option<&Path>, rules: &[IgnoreRule]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(dir);
+    for rule in rules {
+        let _ = builder.add_line(source.map(Path::to_path_buf), &rule.pattern);
+    }
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(source: &str, pattern: &str) -> IgnoreRule {
+        IgnoreRule {
+            source: source.to_string(),
+            pattern: pattern.to_string(),
+        }
+    }
+
+    #[test]
+    fn includes_beat_excludes_on_the_command_line() {
+        let stack = IgnoreStack::new(&["*.log".to_string()], &["keep.log".to_string()]).unwrap();
+        assert!(stack.is_ignored(Path::new("./a.log"), false));
+        assert!(!stack.is_ignored(Path::new("./keep.log"), false));
+        assert!(!stack.is_ignored(Path::new("./a.txt"), false));
+    }
+
+    #[test]
+    fn a_later_negation_in_the_same_file_wins() {
+        let stack = IgnoreStack::from_rules(&[
+            rule("./.frzrignore", "*.tmp"),
+            rule("./.frzrignore", "!important.tmp"),
+        ]);
+        assert!(stack.is_ignored(Path::new("./scratch.tmp"), false));
+        assert!(!stack.is_ignored(Path::new("./important.tmp"), false));
+        assert!(!stack.is_ignored(Path::new("./d/important.tmp"), false));
+    }
+
+    #[test]
+    fn an_inner_frzrignore_overrides_an_outer_one() {
+        let stack = IgnoreStack::from_rules(&[
+            rule("./.frzrignore", "*.tmp"),
+            rule("./d/.frzrignore", "!*.tmp"),
+        ]);
+        assert!(!stack.is_ignored(Path::new("./d/x.tmp"), false));
+        // Only explain looks at which .frzrignore files a path is under; during a walk, the
+        // stack never has any it isn't under
+        assert_eq!(
+            stack.explain(Path::new("./d/x.tmp")).map(|r| r.pattern),
+            None
+        );
+        let explained = stack.explain(Path::new("./x.tmp")).unwrap();
+        assert_eq!(
+            (explained.source.as_str(), explained.pattern.as_str()),
+            ("./.frzrignore", "*.tmp")
+        );
+    }
+
+    #[test]
+    fn the_command_line_overrides_every_frzrignore() {
+        let stack = IgnoreStack::from_rules(&[
+            rule(COMMAND_LINE_SOURCE, "!*.tmp"),
+            rule(COMMAND_LINE_SOURCE, "cache/"),
+            rule("./.frzrignore", "*.tmp"),
+            rule("./d/.frzrignore", "!cache/"),
+        ]);
+        assert!(!stack.is_ignored(Path::new("./x.tmp"), false));
+        assert!(stack.is_ignored(Path::new("./d/cache"), true));
+        // A trailing `/` only matches directories
+        assert!(!stack.is_ignored(Path::new("./d/cache"), false));
+    }
+}
@@ -0,0 +1,155 @@
+// What `frzr fsck` looks for: damage SQLite itself can see, and anything that doesn't add up
+// given how frzr writes the DB. Every write `check` makes happens in a transaction, so none of
+// these should turn up however a run was cut short; if one does, it is a bug in frzr, or the
+// DB was damaged underneath it
+
+use sqlite::Connection;
+use sqlite::State;
+
+use crate::hashing::HashAlgorithm;
+
+// Everything that is wrong, one line each, in no particular order. Empty when all is well
+pub fn check_consistency(db: &Connection) -> Result<Vec<String>, sqlite::Error> {
+    let mut problems = Vec::new();
+    let mut statement = db.prepare("PRAGMA integrity_check;")?;
+    while let State::Row = statement.next()? {
+        let message = statement.read::<String>(0)?;
+        if message != "ok" {
+            problems.push(format!("integrity check: {}", message));
+        }
+    }
+    let mut statement = db.prepare("PRAGMA foreign_key_check;")?;
+    while let State::Row = statement.next()? {
+        problems.push(format!(
+            "{} row {} refers to a {} row that doesn't exist",
+            statement.read::<String>(0)?,
+            statement.read::<Option<i64>>(1)?.unwrap_or(0),
+            statement.read::<String>(2)?,
+        ));
+    }
+    rows(
+        db,
+        &mut problems,
+        "SELECT id, status FROM run \
+            WHERE status NOT IN ('running', 'aborted', 'finished') \
+                OR (status = 'finished') != (end_time IS NOT NULL);",
+        |statement| {
+            Ok(format!(
+                "run {} is {} but {} an end time",
+                statement.read::<i64>(0)?,
+                statement.read::<String>(1)?,
+                if statement.read::<String>(1)? == "finished" {
+                    "has no"
+                } else {
+                    "has"
+                }
+            ))
+        },
+    )?;
+    // A run writes a path at most once; resuming it carries on from what it already wrote
+    rows(
+        db,
+        &mut problems,
+        "SELECT run_id, path_id, COUNT(*) FROM file_entry \
+            GROUP BY run_id, path_id HAVING COUNT(*) > 1;",
+        |statement| {
+            Ok(format!(
+                "run {} has {} entries for path {}",
+                statement.read::<i64>(0)?,
+                statement.read::<i64>(2)?,
+                statement.read::<i64>(1)?
+            ))
+        },
+    )?;
+    rows(
+        db,
+        &mut problems,
+        "SELECT id FROM file_entry WHERE file_type = 'file' AND digest IS NULL;",
+        |statement| {
+            Ok(format!(
+                "file entry {} is a file without a digest",
+                statement.read::<i64>(0)?
+            ))
+        },
+    )?;
+    let mut statement = db.prepare(
+        "SELECT id, algorithm, length(digest) FROM file_entry WHERE digest IS NOT NULL \
+            UNION ALL SELECT file_entry_id, algorithm, length(digest) FROM file_digest;",
+    )?;
+    while let State::Row = statement.next()? {
+        let id = statement.read::<i64>(0)?;
+        let algorithm = statement.read::<i64>(1)?;
+        let len = statement.read::<i64>(2)?;
+        match HashAlgorithm::from_id(algorithm) {
+            Some(known) if digest_len(known) == len => (),
+            Some(known) => problems.push(format!(
+                "file entry {} has a {} digest {} bytes long",
+                id,
+                known.as_str(),
+                len
+            )),
+            None => problems.push(format!(
+                "file entry {} has a digest from unknown algorithm {}",
+                id, algorithm
+            )),
+        }
+    }
+    rows(
+        db,
+        &mut problems,
+        "SELECT file_entry_id FROM file_chunks WHERE length(digests) % 8 != 0;",
+        |statement| {
+            Ok(format!(
+                "file entry {} has chunk digests cut short",
+                statement.read::<i64>(0)?
+            ))
+        },
+    )?;
+    rows(
+        db,
+        &mut problems,
+        "SELECT path.id, last_seen_run FROM path \
+            WHERE last_seen_run IS NOT NULL \
+                AND last_seen_run NOT IN (SELECT id FROM run);",
+        |statement| {
+            Ok(format!(
+                "path {} was last seen by run {}, which doesn't exist",
+                statement.read::<i64>(0)?,
+                statement.read::<i64>(1)?
+            ))
+        },
+    )?;
+    rows(
+        db,
+        &mut problems,
+        "SELECT baseline.path_id, file_entry.path_id FROM baseline \
+            JOIN file_entry ON file_entry.id = baseline.file_entry_id \
+            WHERE file_entry.path_id != baseline.path_id;",
+        |statement| {
+            Ok(format!(
+                "the baseline for path {} is an entry for path {}",
+                statement.read::<i64>(0)?,
+                statement.read::<i64>(1)?
+            ))
+        },
+    )?;
+    Ok(problems)
+}
+
+// Runs query, adding a problem for every row it returns
+fn rows(
+    db: &Connection,
+    problems: &mut Vec<String>,
+    query: &str,
+    describe: impl Fn(&sqlite::Statement) -> Result<String, sqlite::Error>,
+) -> Result<(), sqlite::Error> {
+    let mut statement = db.prepare(query)?;
+    while let State::Row = statement.next()? {
+        problems.push(describe(&statement)?);
+    }
+    Ok(())
+}
+
+fn digest_len(algorithm: HashAlgorithm) -> i64 {
+    algorithm.hasher().finish().len() as i64 / 2
+}
@@ -0,0 +1,276 @@
+// The digests frzr knows how to take of a file. Digests are passed around as lowercase hex, the
+// way the matching `*sum` tool prints them, and stored as the raw bytes
+
+use sha2::{Digest, Sha256, Sha512};
+
+// The algorithm used when nothing else was asked for at `frzr init`
+pub const DEFAULT_ALGORITHM: HashAlgorithm = HashAlgorithm::Sha256;
+
+// The names accepted on the command line, in the order --help lists them
+pub const ALGORITHM_NAMES: [&str; 5] = ["sha256", "sha512", "blake3", "xxh3", "crc32c"];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+    // 64-bit XXH3; fast, but only good for catching accidents
+    Xxh3,
+    Crc32c,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Crc32c => "crc32c",
+        }
+    }
+
+    pub fn from_str(algorithm: &str) -> Option<HashAlgorithm> {
+        match algorithm {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "xxh3" => Some(HashAlgorithm::Xxh3),
+            "crc32c" => Some(HashAlgorithm::Crc32c),
+            _ => None,
+        }
+    }
+
+    // How the DB refers to the algorithm. These never change, and are never reused
+    pub fn id(&self) -> i64 {
+        match self {
+            HashAlgorithm::Sha256 => 1,
+            HashAlgorithm::Sha512 => 2,
+            HashAlgorithm::Blake3 => 3,
+            HashAlgorithm::Xxh3 => 4,
+            HashAlgorithm::Crc32c => 5,
+        }
+    }
+
+    pub fn from_id(id: i64) -> Option<HashAlgorithm> {
+        match id {
+            1 => Some(HashAlgorithm::Sha256),
+            2 => Some(HashAlgorithm::Sha512),
+            3 => Some(HashAlgorithm::Blake3),
+            4 => Some(HashAlgorithm::Xxh3),
+            5 => Some(HashAlgorithm::Crc32c),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+            HashAlgorithm::Crc32c => Hasher::Crc32c(0),
+        }
+    }
+}
+
+// A digest in progress
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    Crc32c(u32),
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha512(hasher) => hasher.update(bytes),
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+            Hasher::Xxh3(hasher) => hasher.update(bytes),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, bytes),
+        }
+    }
+
+    pub fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha512(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
+            Hasher::Crc32c(crc) => format!("{:08x}", crc),
+        }
+    }
+}
+
+// The bytes a hex digest stands for, as the DB keeps them
+pub fn digest_to_bytes(digest: &str) -> Vec<u8> {
+    (0..digest.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&digest[i * 2..i * 2 + 2], 16).ok())
+        .collect()
+}
+
+pub fn digest_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// What each chunk of a file is hashed with. Chunk digests only have to tell versions of the same
+// chunk apart, so a small fast one will do
+pub const CHUNK_ALGORITHM: HashAlgorithm = HashAlgorithm::Xxh3;
+
+// The digest of every chunk_size bytes of a file, in order, packed back to back as 8 big-endian
+// bytes each. The last chunk is whatever is left over
+#[derive(Clone)]
+pub struct Chunks {
+    pub chunk_size: u64,
+    pub digests: Vec<u8>,
+}
+
+const CHUNK_DIGEST_LEN: usize = 8;
+
+impl Chunks {
+    pub fn count(&self) -> u64 {
+        (self.digests.len() / CHUNK_DIGEST_LEN) as u64
+    }
+
+    pub fn digest(&self, index: u64) -> Option<&[u8]> {
+        let start = index as usize * CHUNK_DIGEST_LEN;
+        self.digests.get(start..start + CHUNK_DIGEST_LEN)
+    }
+
+    // (first, last) chunk indexes of every run of chunks that differ, counting chunks only one
+    // of the two has as differing. None if the chunk sizes differ and there's no telling
+    pub fn changed_ranges(&self, other: &Chunks) -> Option<Vec<(u64, u64)>> {
+        if self.chunk_size != other.chunk_size {
+            return None;
+        }
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for index in 0..self.count().max(other.count()) {
+            if self.digest(index) == other.digest(index) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == index => *last = index,
+                _ => ranges.push((index, index)),
+            }
+        }
+        Some(ranges)
+    }
+}
+
+// The digest of one whole chunk, the same as a ChunkHasher would have taken
+pub fn chunk_digest(bytes: &[u8]) -> [u8; CHUNK_DIGEST_LEN] {
+    xxhash_rust::xxh3::xxh3_64(bytes).to_be_bytes()
+}
+
+// Hashes each chunk of a file as the bytes go past, however the reads line up with the chunks
+pub struct ChunkHasher {
+    chunk_size: u64,
+    current: xxhash_rust::xxh3::Xxh3,
+    // Bytes that have gone into current
+    in_current: u64,
+    digests: Vec<u8>,
+}
+
+impl ChunkHasher {
+    pub fn new(chunk_size: u64) -> ChunkHasher {
+        ChunkHasher {
+            chunk_size,
+            current: xxhash_rust::xxh3::Xxh3::new(),
+            in_current: 0,
+            digests: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let room = (self.chunk_size - self.in_current).min(bytes.len() as u64) as usize;
+            self.current.update(&bytes[..room]);
+            self.in_current += room as u64;
+            bytes = &bytes[room..];
+            if self.in_current == self.chunk_size {
+                self.end_chunk();
+            }
+        }
+    }
+
+    fn end_chunk(&mut self) {
+        self.digests
+            .extend_from_slice(&self.current.digest().to_be_bytes());
+        self.current.reset();
+        self.in_current = 0;
+    }
+
+    pub fn finish(mut self) -> Chunks {
+        if self.in_current > 0 {
+            self.end_chunk();
+        }
+        Chunks {
+            chunk_size: self.chunk_size,
+            digests: self.digests,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks_of(bytes: &[u8], chunk_size: u64) -> Chunks {
+        let mut hasher = ChunkHasher::new(chunk_size);
+        hasher.update(bytes);
+        hasher.finish()
+    }
+
+    #[test]
+    fn chunk_hasher_ends_with_a_partial_chunk() {
+        let bytes = b"0123456789";
+        let chunks = chunks_of(bytes, 4);
+        assert_eq!(chunks.count(), 3);
+        assert_eq!(chunks.digest(0), Some(&chunk_digest(b"0123")[..]));
+        assert_eq!(chunks.digest(2), Some(&chunk_digest(b"89")[..]));
+        assert_eq!(chunks.digest(3), None);
+        // A file that fills its last chunk has no empty one after it, and an empty file none
+        assert_eq!(chunks_of(&bytes[..8], 4).count(), 2);
+        assert_eq!(chunks_of(b"", 4).count(), 0);
+    }
+
+    #[test]
+    fn chunk_hasher_does_not_care_how_the_reads_line_up() {
+        let bytes = b"0123456789";
+        let mut hasher = ChunkHasher::new(4);
+        for piece in [&bytes[..3], &bytes[3..4], &bytes[4..9], &bytes[9..]] {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish().digests, chunks_of(bytes, 4).digests);
+    }
+
+    #[test]
+    fn changed_ranges_joins_neighbouring_chunks() {
+        let old = chunks_of(b"aaaabbbbccccdddde", 4);
+        let new = chunks_of(b"aaaaXbbbYcccddddf", 4);
+        assert_eq!(old.changed_ranges(&new), Some(vec![(1, 2), (4, 4)]));
+        assert_eq!(old.changed_ranges(&old), Some(vec![]));
+    }
+
+    #[test]
+    fn changed_ranges_counts_chunks_only_one_side_has() {
+        let old = chunks_of(b"aaaabbbbcc", 4);
+        // Growing the partial last chunk changes it, and adds the ones after it
+        let new = chunks_of(b"aaaabbbbccccdddd", 4);
+        assert_eq!(old.changed_ranges(&new), Some(vec![(2, 3)]));
+        assert_eq!(new.changed_ranges(&old), Some(vec![(2, 3)]));
+    }
+
+    #[test]
+    fn changed_ranges_cannot_compare_different_chunk_sizes() {
+        let bytes = b"aaaabbbb";
+        assert!(chunks_of(bytes, 4)
+            .changed_ranges(&chunks_of(bytes, 8))
+            .is_none());
+    }
+}
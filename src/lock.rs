@@ -0,0 +1,245 @@
+// One writer at a time per DB. Verbs that write take an flock(2) on .frzr/lock for as long as
+// they run; the kernel lets go of it when the process ends, however it ends, so a lock can't be
+// left behind. What's in the file is only there to say who has it: the pid, host, verb and
+// start time of the holder, cleared again on the way out. A holder that was killed never gets
+// to clear it, which is how a stale lock shows up: the file is locked by nobody but still says
+// who had it. Verbs that only read don't take the lock, unless the DB has to be upgraded first,
+// and then they let go of it again as soon as it's upgraded
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const LOCK_PATH: &str = "./.frzr/lock";
+
+// Kept open, and so locked, until the process exits
+static HELD: Mutex<Option<fs::File>> = Mutex::new(None);
+
+// Whoever has (or had) the lock, as they wrote it down
+pub struct Holder {
+    pub pid: u32,
+    host: String,
+    pub verb: String,
+    // Seconds since the epoch
+    started: u64,
+}
+
+impl Holder {
+    fn parse(contents: &str) -> Option<Holder> {
+        let mut lines = contents.lines();
+        Some(Holder {
+            pid: lines.next()?.strip_prefix("pid ")?.parse().ok()?,
+            host: lines.next()?.strip_prefix("host ")?.to_string(),
+            verb: lines.next()?.strip_prefix("verb ")?.to_string(),
+            started: lines.next()?.strip_prefix("started ")?.parse().ok()?,
+        })
+    }
+
+    // Whether the holder still looks to be running. Only a process on this host can be looked
+    // for; one on another host is taken at its word
+    pub fn is_alive(&self) -> bool {
+        if self.host != hostname() {
+            return true;
+        }
+        // Someone else's process can't be signalled, but is still there
+        let signalled = unsafe { libc::kill(self.pid as libc::pid_t, 0) } == 0;
+        signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`frzr {}` (pid {} on {}, started {})",
+            self.verb,
+            self.pid,
+            self.host,
+            utc_timestamp(self.started)
+        )
+    }
+}
+
+impl fmt::Debug for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    // Someone else is writing; None if they haven't said who yet
+    Held(Option<Holder>),
+    Io(io::Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockError::Held(Some(holder)) => write!(f, "{} is writing to the DB", holder),
+            LockError::Held(None) => write!(f, "another frzr is writing to the DB"),
+            LockError::Io(e) => write!(f, "couldn't lock {}: {}", LOCK_PATH, e),
+        }
+    }
+}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> LockError {
+        LockError::Io(e)
+    }
+}
+
+// Takes the lock for the rest of the process, for verb. If someone else has it, either waits
+// for them or says who they are. Returns whoever left a stale lock behind, if anyone did
+pub fn acquire(verb: &str, wait: bool) -> Result<Option<Holder>, LockError> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(LOCK_PATH)?;
+    let operation = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock => return Err(LockError::Held(read_holder(&mut file)?)),
+            _ => return Err(e.into()),
+        }
+    }
+    let stale = read_holder(&mut file)?;
+    file.set_len(0)?;
+    file.rewind()?;
+    let started = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    write!(
+        file,
+        "pid {}\nhost {}\nverb {}\nstarted {}\n",
+        std::process::id(),
+        hostname(),
+        verb,
+        started
+    )?;
+    file.sync_all()?;
+    *HELD.lock().unwrap() = Some(file);
+    unsafe { libc::atexit(release_at_exit) };
+    Ok(stale)
+}
+
+// Who the lock file says has the lock, if anyone. The lock itself is left alone, since readers
+// never take it, so the answer can be out of date as soon as it's given
+pub fn holder() -> Result<Option<Holder>, io::Error> {
+    match fs::File::open(LOCK_PATH) {
+        Ok(mut file) => read_holder(&mut file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_holder(file: &mut fs::File) -> Result<Option<Holder>, io::Error> {
+    let mut contents = String::new();
+    file.rewind()?;
+    file.read_to_string(&mut contents)?;
+    Ok(Holder::parse(&contents))
+}
+
+// Wipes who had the lock, so that the next holder doesn't take it for stale, and lets go of it
+pub fn release() {
+    if let Ok(mut held) = HELD.lock() {
+        if let Some(file) = held.take() {
+            let _ = file.set_len(0);
+        }
+    }
+}
+
+// Runs from exit(3), for a process that held on to the lock to the end. The lock itself would
+// go with the process anyway
+extern "C" fn release_at_exit() {
+    release();
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return "unknown host".to_string();
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+// The same shape as SQLite's CURRENT_TIMESTAMP, which is what the rest of the DB has
+fn utc_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // Days to a civil date, from Howard Hinnant's chrono-compatible algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_acquire_writes() {
+        let holder = Holder::parse("pid 42\nhost vm\nverb check\nstarted 951782400\n").unwrap();
+        assert_eq!((holder.pid, holder.verb.as_str()), (42, "check"));
+        assert_eq!(
+            holder.to_string(),
+            "`frzr check` (pid 42 on vm, started 2000-02-29 00:00:00 UTC)"
+        );
+    }
+
+    #[test]
+    fn a_cleared_or_half_written_lock_file_has_no_holder() {
+        assert!(Holder::parse("").is_none());
+        assert!(Holder::parse("pid 42\nhost vm\n").is_none());
+        assert!(Holder::parse("pid -1\nhost vm\nverb check\nstarted 0\n").is_none());
+    }
+
+    #[test]
+    fn timestamps_get_leap_days_right() {
+        for (seconds, timestamp) in [
+            (0, "1970-01-01 00:00:00"),
+            (946684799, "1999-12-31 23:59:59"),
+            // 2000 is a leap year, being divisible by 400
+            (951782400, "2000-02-29 00:00:00"),
+            (951868800, "2000-03-01 00:00:00"),
+            (1709251199, "2024-02-29 23:59:59"),
+            // 2100 isn't, being divisible by 100
+            (4107501296, "2100-02-28 12:34:56"),
+            (4107542400, "2100-03-01 00:00:00"),
+        ] {
+            assert_eq!(utc_timestamp(seconds), format!("{} UTC", timestamp));
+        }
+    }
+}
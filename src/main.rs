@@ -1,24 +1,130 @@
+use std::borrow::Borrow;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::fs;
-use std::fs::DirEntry;
 use std::io;
 use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
-
-use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use sqlite::Connection;
 use sqlite::State;
+use sqlite::Statement;
 
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
 
+use std::ffi::OsStr;
 use std::ffi::OsString;
 
-// TODO: Example how to import `args` from clap so we can pass options to the "verbs" of frzr:
-// use clap::{arg, Command};
-use clap::{Command};
+use clap::{arg, ArgMatches, Command};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use signal_hook::iterator::Signals;
+
+mod frzrignore;
+use frzrignore::{IgnoreRule, IgnoreStack};
+
+mod fsck;
+
+mod hashing;
+use hashing::{
+    digest_to_bytes, digest_to_hex, ChunkHasher, Chunks, HashAlgorithm, ALGORITHM_NAMES,
+    CHUNK_ALGORITHM, DEFAULT_ALGORITHM,
+};
+
+mod lock;
+use lock::LockError;
+
+mod mounts;
+use mounts::Mount;
+
+mod parity;
+
+mod progress;
+use progress::Progress;
+
+mod schema;
+
+mod throttle;
+use throttle::{Limits, RateLimiter};
+
+mod walk;
+use walk::Walker;
+
+// Exit codes for `check`, so that scheduled jobs can tell bitrot apart from frzr itself failing
+const EXIT_CLEAN: i32 = 0;
+const EXIT_CHANGES: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+// Like println!, but a closed stdout (think `frzr dump | head`) ends the process quietly instead
+// of panicking
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        write_stdout(format_args!($($arg)*))
+    };
+}
+
+fn write_stdout(args: std::fmt::Arguments) {
+    let mut stdout = io::stdout().lock();
+    let written = stdout.write_fmt(args).and_then(|_| stdout.write_all(b"\n"));
+    match written {
+        Ok(_) => (),
+        // Same exit status as if SIGPIPE had killed us
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => exit(128 + libc::SIGPIPE),
+        Err(e) => panic!("failed printing to stdout: {}", e),
+    }
+}
+
+// Set by the SIGINT/SIGTERM handler to the number of the signal, so that a `check` in progress
+// can wind down cleanly: the workers stop taking files, and whatever has been hashed gets
+// written out before the run is marked aborted
+static STOP_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+fn stop_requested() -> Option<i32> {
+    match STOP_SIGNAL.load(Ordering::Relaxed) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+fn handle_stop_signals() -> Result<(), io::Error> {
+    let already_stopping = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        // A second signal while winding down kills the process outright
+        flag::register_conditional_shutdown(signal, 128 + signal, Arc::clone(&already_stopping))?;
+        flag::register(signal, Arc::clone(&already_stopping))?;
+    }
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            STOP_SIGNAL.store(signal, Ordering::Relaxed);
+        }
+    });
+    Ok(())
+}
+
+fn signal_name(signal: i32) -> String {
+    match signal_hook::low_level::signal_name(signal) {
+        Some(name) => name.to_string(),
+        None => format!("signal {}", signal),
+    }
+}
 
 fn cli() -> Command<'static> {
     Command::new("frzr")
@@ -29,26 +135,205 @@ fn cli() -> Command<'static> {
         .subcommand(
             //TODO maybe add `--start-over` argument to `init` for blowing away .frzr and starting
             //     fresh?
-            Command::new("init").about("Initialize the frzr db for the current directory"),
+            Command::new("init")
+                .about("Initialize the frzr db for the current directory")
+                .arg(
+                    arg!(--hash <ALGORITHM> "The hash `check` uses unless told otherwise")
+                        .required(false)
+                        .value_parser(ALGORITHM_NAMES)
+                        .default_value(DEFAULT_ALGORITHM.as_str()),
+                )
+                .arg(
+                    arg!(--"chunk-size" <SIZE> "Have `check` also hash every SIZE bytes, e.g. 1MiB")
+                        .required(false)
+                        .value_parser(throttle::parse_byte_size),
+                ),
         )
         .subcommand(
             // TODO dump could probably take a run id and dump its checksums while defaulting to
             //      the latest
-            Command::new("dump").about("Dump the latest run's checksums in `shasum` format"),
+            Command::new("dump")
+                .about("Dump the latest run's checksums in `shasum` format")
+                .arg(
+                    arg!(--hash <ALGORITHM> "Which digest to dump [default: the run's own]")
+                        .required(false)
+                        .value_parser(ALGORITHM_NAMES),
+                ),
         )
         .subcommand(
             // TODO status could take a run id, too?
-            // TODO will status ever show info about a currently-running `frzr` process?
             // TODO should this be `stats` instead of `status`?
-            Command::new("status").about("Show the status of the latest run"),
+            Command::new("status")
+                .about("Show the progress of a running check and a summary of the latest run"),
         )
         .subcommand(
-            // TODO Will check also print out if there are differences in what is in the DB vs what
-            //      is on the disk?
-            // TODO Maybe the default behavior will be to walk starting at CWD, but you can pass
-            //      --full to do a full scan regardless of CWD
+            // TODO Maybe the default behavior will be to walk starting at CWD, but there could be
+            //      a flag to scan the whole freezer regardless of CWD
             Command::new("check")
-                .about("Walk the filesystem, starting at CWD, compute and store checksums"),
+                .about("Walk the filesystem, starting at CWD, compute and store checksums")
+                .long_about(
+                    "Walk the filesystem, starting at CWD, compute and store checksums, and \
+                     compare them against the baseline. Exits 0 when nothing changed, 1 when \
+                     files were modified, added or went missing, and 2 on errors, including \
+                     files that couldn't be read. The first \
+                     run establishes the baseline. On SIGINT or SIGTERM, whatever has been \
+                     hashed is saved and the run is marked aborted, to be finished later with \
+                     --resume.",
+                )
+                .arg(
+                    arg!(--quick "Reuse the previous run's hash for files whose stat is unchanged")
+                        .conflicts_with("full"),
+                )
+                .arg(arg!(--full "Reread and hash every file (the default)"))
+                .arg(
+                    arg!(-j --jobs <N> "How many files to hash at once [default: number of CPUs]")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    arg!(--"max-read-rate" <RATE> "Cap how fast files are read, e.g. 50MiB/s")
+                        .required(false)
+                        .value_parser(throttle::parse_byte_rate),
+                )
+                .arg(
+                    arg!(--"max-files-per-sec" <N> "Cap how many files are checked per second")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(arg!(--nice "Run at the lowest CPU and I/O scheduling priority"))
+                .arg(
+                    arg!(--hash <ALGORITHM> "Hash with this instead of the default from `init`")
+                        .required(false)
+                        .value_parser(ALGORITHM_NAMES),
+                )
+                .arg(
+                    arg!(--"also-hash" <ALGORITHM> "Take this digest too, in the same read")
+                        .required(false)
+                        .value_parser(ALGORITHM_NAMES)
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"chunk-size" <SIZE> "Hash every SIZE bytes too, to locate damage")
+                        .required(false)
+                        .value_parser(throttle::parse_byte_size),
+                )
+                .arg(
+                    arg!(--resume "Finish the latest run if it was interrupted, instead of \
+                                    starting over"),
+                )
+                .arg(
+                    arg!(--symlinks <POLICY> "Record symlinks as links, follow them, or skip them")
+                        .required(false)
+                        .value_parser(["record", "follow", "skip"])
+                        .default_value("record"),
+                )
+                .arg(arg!(
+                    -x --"one-file-system" "Don't go into directories on other filesystems"
+                ))
+                .arg(
+                    arg!(--exclude <PATTERN> "Skip files matching a .frzrignore-style pattern")
+                        .required(false)
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--include <PATTERN> "Check files matching a pattern, even if ignored")
+                        .required(false)
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"batch-size" <N> "How many files to write to the DB per transaction \
+                                              [default: 1000]")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(arg!(--benchmark "Report how fast files were hashed and written at the end"))
+                .arg(arg!(--wait "If another frzr is writing to the DB, wait for it to finish")),
+        )
+        .subcommand(
+            // TODO report could take two run ids to compare arbitrary runs
+            Command::new("report")
+                .about("Compare the latest run against the previous one, reading only the DB"),
+        )
+        .subcommand(
+            Command::new("rehash")
+                .about("Move the baseline to another hash algorithm, verifying every file")
+                .long_about(
+                    "Move the baseline to another hash algorithm. Each file is read once, to \
+                     check it against its baseline digest and take the new one; only files that \
+                     verify get the new digest, and the rest are reported. From then on, \
+                     `check` hashes with the new algorithm. Run it again to pick up where an \
+                     interrupted rehash left off.",
+                )
+                .arg(
+                    arg!(--to <ALGORITHM> "The algorithm to move to").value_parser(ALGORITHM_NAMES),
+                ),
+        )
+        .subcommand(
+            Command::new("protect")
+                .about("Write parity for baseline files, so that `repair` can fix damage to them")
+                .long_about(
+                    "Write Reed-Solomon parity for baseline files under each PATH into \
+                     .frzr/parity, so that `repair` can rebuild damaged parts of them. Each \
+                     file is read once, and only protected if it still matches the baseline. \
+                     Protecting a file again replaces its parity.",
+                )
+                .arg(arg!(<PATH> ... "Protect baseline files at PATH, or anywhere under it"))
+                .arg(
+                    arg!(--parity <PERCENT> "How much parity to write, as a percent of the file")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64).range(1..=100))
+                        .default_value("10"),
+                )
+                .arg(
+                    arg!(--"block-size" <SIZE> "Size of the blocks parity can rebuild, at most \
+                                                4MiB [default: the chunk size from `init`, or \
+                                                1MiB]")
+                        .required(false)
+                        .value_parser(parity::parse_block_size),
+                ),
+        )
+        .subcommand(
+            Command::new("repair")
+                .about("Rebuild damaged parts of protected files from their parity")
+                .long_about(
+                    "Rebuild damaged parts of protected files from their parity. Blocks that \
+                     don't match the digests taken by `protect` are rebuilt in place and the \
+                     whole file is checked against its baseline digest afterwards. A file whose \
+                     baseline has changed since it was protected is left alone, since its \
+                     parity would put the old contents back; it needs `frzr protect` again. \
+                     Exits 0 when every file is intact or repaired, 1 when some damage was more \
+                     than the parity could cover or some parity is stale, and 2 on errors.",
+                )
+                .arg(arg!(<PATH> ... "Repair protected files at PATH, or anywhere under it")),
+        )
+        .subcommand(
+            Command::new("fsck")
+                .about("Check the DB for damage and for records that don't add up")
+                .long_about(
+                    "Check the DB for damage and for records that don't add up. Runs SQLite's \
+                     own integrity and foreign key checks, then checks that runs, entries, \
+                     digests and the baseline agree with each other. Exits 0 when the DB is \
+                     consistent, 1 when something is wrong with it, and 2 on errors.",
+                ),
+        )
+        .subcommand(
+            Command::new("resolve")
+                .about("Accept changes from the latest run into the trusted baseline")
+                .long_about(
+                    "Accept changes from the latest run into the trusted baseline. Without \
+                     --accept or --accept-all, each pending change is presented for a decision.",
+                )
+                .arg(
+                    arg!(--accept <PATH> "Accept changes to PATH, or to anything under it")
+                        .required(false)
+                        .multiple_values(true)
+                        .conflicts_with("accept-all"),
+                )
+                .arg(arg!(--"accept-all" "Accept every pending change"))
+                .arg(
+                    arg!(--by <NAME> "Who is accepting the changes (defaults to $USER)")
+                        .required(false),
+                ),
         )
     //TODO Remove below commented-out stanza -- leaving for learning:
     // .subcommand(
@@ -65,40 +350,108 @@ fn cli() -> Command<'static> {
 //     vec![arg!(-m --message <MESSAGE>).required(false)]
 // }
 
-// TODO: How to not crash when stdout is closed?  Answer: There are
-//      two paths: register our own handler for SIGPIPE, or just
-//      handle the broken pipe error that's returned as standard
-//
-//      I think the best path forward is just to use the write macros
-//      and handle the standard broken pipe error
 fn main() {
     let matches = cli().get_matches();
 
     match matches.subcommand() {
-        Some(("init", _)) => {
-            // TODO: do we need to match `sub_matches`; here `_`?
-            init();
+        Some(("init", sub_matches)) => {
+            init(
+                algorithm_of(sub_matches, "hash").unwrap(),
+                sub_matches.get_one::<u64>("chunk-size").copied(),
+            );
+        }
+        Some(("dump", sub_matches)) => {
+            dump(algorithm_of(sub_matches, "hash"));
+        }
+        Some(("check", sub_matches)) => {
+            let mode = if sub_matches.contains_id("quick") {
+                CheckMode::Quick
+            } else {
+                CheckMode::Full
+            };
+            let jobs = match sub_matches.get_one::<u64>("jobs") {
+                Some(jobs) => *jobs as usize,
+                None => thread::available_parallelism().map_or(1, |n| n.get()),
+            };
+            let limits = Limits {
+                read_rate: sub_matches
+                    .get_one::<u64>("max-read-rate")
+                    .map(|rate| RateLimiter::new(*rate)),
+                file_rate: sub_matches
+                    .get_one::<u64>("max-files-per-sec")
+                    .map(|rate| RateLimiter::new(*rate)),
+            };
+            check(CheckOptions {
+                mode,
+                resume: sub_matches.contains_id("resume"),
+                symlinks: value_of(sub_matches, "symlinks", SymlinkPolicy::from_str).unwrap(),
+                excludes: strings_of(sub_matches, "exclude"),
+                includes: strings_of(sub_matches, "include"),
+                one_file_system: sub_matches.contains_id("one-file-system"),
+                hash: algorithm_of(sub_matches, "hash"),
+                chunk_size: sub_matches.get_one::<u64>("chunk-size").copied(),
+                also_hash: strings_of(sub_matches, "also-hash")
+                    .iter()
+                    .filter_map(|algorithm| HashAlgorithm::from_str(algorithm))
+                    .collect(),
+                jobs,
+                limits,
+                nice: sub_matches.contains_id("nice"),
+                batch_size: sub_matches
+                    .get_one::<u64>("batch-size")
+                    .map_or(WRITE_BATCH_SIZE, |batch_size| *batch_size as usize),
+                benchmark: sub_matches.contains_id("benchmark"),
+                wait: sub_matches.contains_id("wait"),
+            });
         }
-        Some(("dump", _)) => {
-            dump();
+        Some(("report", _)) => {
+            report();
         }
-        Some(("check", _)) => {
-            check();
+        Some(("rehash", sub_matches)) => {
+            rehash(algorithm_of(sub_matches, "to").unwrap());
+        }
+        Some(("protect", sub_matches)) => {
+            protect(
+                strings_of(sub_matches, "PATH"),
+                *sub_matches.get_one::<u64>("parity").unwrap(),
+                sub_matches.get_one::<u64>("block-size").copied(),
+            );
+        }
+        Some(("repair", sub_matches)) => {
+            repair(strings_of(sub_matches, "PATH"));
+        }
+        Some(("fsck", _)) => {
+            fsck();
+        }
+        Some(("status", _)) => {
+            status();
+        }
+        Some(("resolve", sub_matches)) => {
+            let accept_paths = strings_of(sub_matches, "accept");
+            let accepted_by = match sub_matches.get_one::<String>("by") {
+                Some(by) => by.clone(),
+                None => std::env::var("USER").unwrap_or_else(|_| String::from("unknown")),
+            };
+            resolve(
+                accept_paths,
+                sub_matches.contains_id("accept-all"),
+                accepted_by,
+            );
         }
         Some(("stash", sub_matches)) => {
             let stash_command = sub_matches.subcommand().unwrap_or(("push", sub_matches));
             match stash_command {
                 ("apply", sub_matches) => {
                     let stash = sub_matches.get_one::<String>("STASH");
-                    println!("Applying {:?}", stash);
+                    outln!("Applying {:?}", stash);
                 }
                 ("pop", sub_matches) => {
                     let stash = sub_matches.get_one::<String>("STASH");
-                    println!("Popping {:?}", stash);
+                    outln!("Popping {:?}", stash);
                 }
                 ("push", sub_matches) => {
                     let message = sub_matches.get_one::<String>("message");
-                    println!("Pushing {:?}", message);
+                    outln!("Pushing {:?}", message);
                 }
                 (name, _) => {
                     unreachable!("Unsupported subcommand `{}`", name)
@@ -111,23 +464,36 @@ fn main() {
                 .into_iter()
                 .flatten()
                 .collect::<Vec<_>>();
-            println!("Calling out to {:?} with {:?}", ext, args);
+            outln!("Calling out to {:?} with {:?}", ext, args);
         }
         _ => unreachable!(), // If all subcommands are defined above, anything else is unreachabe!()
     }
 }
 
-fn dump() {
-    let db = match open_and_initialize_db() {
-        Ok(db) => db,
-        Err(e) => {
-            println!(
-                "There was a problem opening or initializing the DB: {:?}",
-                e
-            );
-            exit(1);
-        }
-    };
+// The value parser only lets through names from_str knows
+fn algorithm_of(matches: &ArgMatches, id: &str) -> Option<HashAlgorithm> {
+    value_of(matches, id, HashAlgorithm::from_str)
+}
+
+// The argument's value, turned into whatever it names; None if it wasn't given
+fn value_of<T>(matches: &ArgMatches, id: &str, from_str: fn(&str) -> Option<T>) -> Option<T> {
+    matches
+        .get_one::<String>(id)
+        .and_then(|value| from_str(value))
+}
+
+// Every value given for an argument that can be repeated
+fn strings_of(matches: &ArgMatches, id: &str) -> Vec<String> {
+    matches
+        .get_many::<String>(id)
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+fn dump(algorithm: Option<HashAlgorithm>) {
+    let db = open_db_to_read("dump");
     let mut current_run_id = 0;
     let mut statement = db
         .prepare("SELECT id FROM run ORDER BY id DESC LIMIT 1;")
@@ -138,304 +504,3347 @@ fn dump() {
     if current_run_id == 0 {
         // TODO Return early; no runs in the DB yet
     }
+    let run_algorithm = run_hash_algorithm(&db, current_run_id).unwrap();
+    let algorithm = algorithm.unwrap_or(run_algorithm);
+    // A digest taken with anything but the entry's own algorithm is in file_digest, if it was
+    // taken at all
     let mut statement = db
-        .prepare("SELECT file_name, file_hash FROM file_entry WHERE run_id = ?;")
+        .prepare(format!(
+            "SELECT path.name, CASE WHEN file_entry.algorithm = ?2 THEN file_entry.digest \
+                ELSE (SELECT digest FROM file_digest \
+                    WHERE file_entry_id = file_entry.id AND algorithm = ?2) END AS wanted \
+                FROM file_entry JOIN path ON path.id = file_entry.path_id \
+                WHERE file_entry.id IN (SELECT id FROM ({})) AND file_type = 'file' \
+                    AND wanted IS NOT NULL \
+                ORDER BY path.name;",
+            RUN_STATE
+        ))
         .unwrap();
     statement.bind(1, current_run_id).unwrap();
+    statement.bind(2, algorithm.id()).unwrap();
+    let mut dumped = 0;
     while State::Row == statement.next().unwrap() {
+        dumped += 1;
         let cur_file_name_vec = statement.read::<Vec<u8>>(0).unwrap();
         let cur_file_name = OsString::from_vec(cur_file_name_vec);
         let file_name_path = Path::new(&cur_file_name);
 
-        let cur_file_hash = statement.read::<String>(1).unwrap();
+        let cur_file_hash = digest_to_hex(&statement.read::<Vec<u8>>(1).unwrap());
         // TODO: print nasty filenames better
-        println!("{}  {}", cur_file_hash, file_name_path.display());
+        outln!("{}  {}", cur_file_hash, file_name_path.display());
+    }
+    if dumped == 0 && algorithm != run_algorithm {
+        eprintln!(
+            "Run {} didn't take {} digests; `frzr check --also-hash {}` will",
+            current_run_id,
+            algorithm.as_str(),
+            algorithm.as_str()
+        );
+        exit(EXIT_ERROR);
     }
 }
 
-fn init() {
-    // TODO: This is how I expect init to work:
-    //       1. Check if .frzr directory exists. If it does, bail with message
-    //       2. Create .frzr directory in the current directory
-    //       3. Initialize the DB there
-    let db_path = Path::new("./.frzr/");
-    let path_exists = match db_path.try_exists() {
-        Ok(exists) => exists,
+fn report() {
+    let db = open_db_to_read("report");
+    // Only finished runs are compared; an unfinished run would make every file it didn't get to
+    // look missing
+    let mut run_ids: Vec<i64> = Vec::new();
+    let mut statement = db
+        .prepare("SELECT id FROM run WHERE end_time IS NOT NULL ORDER BY id DESC LIMIT 2;")
+        .unwrap();
+    while State::Row == statement.next().unwrap() {
+        run_ids.push(statement.read::<i64>(0).unwrap());
+    }
+    if run_ids.is_empty() {
+        outln!("No finished runs in the DB yet; run `frzr check` first");
+        return;
+    }
+    let latest_run_id = run_ids[0];
+    match run_ids.get(1) {
+        Some(previous_run_id) => outln!(
+            "Comparing run {} against run {}",
+            latest_run_id,
+            previous_run_id
+        ),
+        None => outln!("Run {} is the only finished run", latest_run_id),
+    }
+    let errors = match load_run_errors(&db, latest_run_id) {
+        Ok(errors) => errors,
         Err(e) => {
-            // TODO Maybe print to stderr here:
-            println!("Error checking for existing .frzr directory; not taking any action!");
-            println!("Error was: {:?}", e);
-            exit(1);
+            outln!("There was a problem reading run {}: {:?}", latest_run_id, e);
+            exit(EXIT_ERROR);
         }
     };
-    if path_exists {
-        // TODO Maybe print to stderr here:
-        println!(".frzr directory already exists; not taking any action!");
-        exit(1);
+    // With only one run, everything in it is new
+    let diff = run_ids
+        .get(1)
+        .map(|previous_run_id| EntryCursor::run(&db, *previous_run_id, false))
+        .transpose()
+        .and_then(|previous| {
+            diff_entries(
+                previous.into_iter().flatten(),
+                EntryCursor::run(&db, latest_run_id, false)?,
+            )
+        });
+    let mut diff = match diff {
+        Ok(diff) => diff,
+        Err(e) => {
+            outln!("There was a problem comparing the runs: {:?}", e);
+            exit(EXIT_ERROR);
+        }
     };
-    match std::fs::create_dir(db_path) {
-        Ok(ok_val) => ok_val,
+    diff.set_aside_unreadable(&errors);
+    // Changes somebody already signed off on with `frzr resolve` aren't worth flagging again
+    let accepted = match diff.set_aside_accepted(&db, latest_run_id) {
+        Ok(accepted) => accepted,
         Err(e) => {
-            // TODO Maybe print to stderr here:
-            println!("Error creating .frzr directory! Error was: {:?}", e);
-            exit(1);
+            outln!("There was a problem reading the baseline: {:?}", e);
+            exit(EXIT_ERROR);
         }
     };
-    // FUTURE: Maybe return the schema version as well as the connection?
-    match open_and_initialize_db() {
-        Ok(db) => db,
+    let ignore_rules = match load_ignore_rules(&db, latest_run_id) {
+        Ok(ignore_rules) => ignore_rules,
         Err(e) => {
-            println!(
-                "There was a problem opening or initializing the DB: {:?}",
-                e
-            );
-            exit(1);
+            outln!("There was a problem reading run {}: {:?}", latest_run_id, e);
+            exit(EXIT_ERROR);
         }
     };
-    // If we get here, then the db is open and ready for business
+    // Runs from before mounts were recorded have none, and can't be compared
+    if let Some(previous_run_id) = run_ids.get(1) {
+        let mounts = load_mounts(&db, *previous_run_id)
+            .and_then(|previous| Ok((previous, load_mounts(&db, latest_run_id)?)));
+        match mounts {
+            Ok((previous, latest)) if !previous.is_empty() && !latest.is_empty() => {
+                print_mount_changes(&previous, &latest, &diff.missing)
+            }
+            Ok(_) => (),
+            Err(e) => {
+                outln!("There was a problem reading the mount points: {:?}", e);
+                exit(EXIT_ERROR);
+            }
+        }
+    }
+    print_errors(&errors);
+    outln!("Unchanged: {}", diff.unchanged);
+    outln!("Accepted:  {}", accepted);
+    print_changes(&diff, &IgnoreStack::from_rules(&ignore_rules));
+    if let Some(previous_run_id) = run_ids.get(1) {
+        let ranges = print_changed_ranges(
+            &db,
+            &ChunkSource::Run(*previous_run_id),
+            &ChunkSource::Run(latest_run_id),
+            &diff,
+        );
+        if let Err(e) = ranges {
+            outln!("There was a problem reading chunk digests: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    }
 }
 
-fn check() {
-    // TODO: Everywhere but `init` ought to probably use a different function to get the DB
-    //       Another possibility would be a param to `open_and_initialize_db` for whether to
-    //       create the DB vs just open and verify schema or something
-    let db = match open_and_initialize_db() {
-        Ok(db) => db,
-        Err(e) => {
-            println!(
-                "There was a problem opening or initializing the DB: {:?}",
-                e
-            );
-            exit(1);
+// Where the chunk digests of one side of a comparison are
+enum ChunkSource {
+    Run(i64),
+    Baseline,
+}
+
+// The chunk digests recorded for file_name, and the size of the file they were taken of
+fn load_chunks(
+    db: &Connection,
+    source: &ChunkSource,
+    file_name: &[u8],
+) -> Result<Option<(Chunks, u64)>, sqlite::Error> {
+    let mut statement = match source {
+        ChunkSource::Run(run_id) => {
+            let mut statement = db.prepare(format!(
+                "SELECT chunk_size, digests, size FROM file_chunks \
+                    JOIN file_entry ON file_entry.id = file_chunks.file_entry_id \
+                    WHERE file_entry.id = ({});",
+                PATH_STATE
+            ))?;
+            statement.bind(1, *run_id)?;
+            statement.bind(2, file_name)?;
+            statement
+        }
+        ChunkSource::Baseline => {
+            let mut statement = db.prepare(
+                "SELECT chunk_size, digests, size FROM file_chunks \
+                    JOIN baseline ON baseline.file_entry_id = file_chunks.file_entry_id \
+                    JOIN file_entry ON file_entry.id = file_chunks.file_entry_id \
+                    JOIN path ON path.id = baseline.path_id \
+                    WHERE path.name = ?;",
+            )?;
+            statement.bind(1, file_name)?;
+            statement
         }
     };
-    // Now, let's iterate over all the files
-    let path_buf: PathBuf = PathBuf::from(".");
+    match statement.next()? {
+        State::Row => {
+            let chunks = Chunks {
+                chunk_size: statement.read::<i64>(0)? as u64,
+                digests: statement.read::<Vec<u8>>(1)?,
+            };
+            Ok(Some((chunks, statement.read::<i64>(2)? as u64)))
+        }
+        State::Done => Ok(None),
+    }
+}
 
-    // TODO: We should have a .frzrignore, or maybe take as a CLI arg?
-    let mut ignore_paths: Vec<PathBuf> = Vec::new();
-    //TODO: Definitely should not ignore `.git` by default:
-    ignore_paths.push(PathBuf::from("./.git"));
-    //TODO: Definitely should not ignore `target` by default:
-    ignore_paths.push(PathBuf::from("./target"));
-    ignore_paths.push(PathBuf::from("./.frzr"));
-    let filenames = match give_me_the_files(path_buf, ignore_paths) {
-        Ok(filenames) => filenames,
-        Err(e) => {
-            println!("There was a problem recursing the filesystem: {:?}", e);
-            exit(1);
+// How many byte ranges to list per file before summing up the rest
+const MAX_RANGES_SHOWN: usize = 8;
+
+// For every file whose contents changed, and that has chunk digests on both sides taken with
+// the same chunk size, the byte ranges that differ. Damage that is confined to a few ranges,
+// and lines up with the disk's sectors, is a different story from a file rewritten end to end
+fn print_changed_ranges(
+    db: &Connection,
+    old: &ChunkSource,
+    new: &ChunkSource,
+    diff: &EntryDiff,
+) -> Result<(), sqlite::Error> {
+    let changed = diff
+        .corrupted
+        .iter()
+        .chain(&diff.edited)
+        .chain(&diff.modified);
+    for file_name in changed {
+        let (old_chunks, old_size) = match load_chunks(db, old, file_name)? {
+            Some(loaded) => loaded,
+            None => continue,
+        };
+        let (new_chunks, new_size) = match load_chunks(db, new, file_name)? {
+            Some(loaded) => loaded,
+            None => continue,
+        };
+        let ranges = match old_chunks.changed_ranges(&new_chunks) {
+            Some(ranges) => ranges,
+            None => continue,
+        };
+        let chunk_size = new_chunks.chunk_size;
+        let file_size = old_size.max(new_size);
+        let changed_chunks: u64 = ranges.iter().map(|(first, last)| last - first + 1).sum();
+        let mut shown: Vec<String> = ranges
+            .iter()
+            .take(MAX_RANGES_SHOWN)
+            .map(|(first, last)| {
+                let end = ((last + 1) * chunk_size).min(file_size);
+                format!("{}-{}", first * chunk_size, end.saturating_sub(1))
+            })
+            .collect();
+        if ranges.len() > MAX_RANGES_SHOWN {
+            shown.push(format!("and {} more", ranges.len() - MAX_RANGES_SHOWN));
         }
-    };
-    let file_iter = filenames.iter();
-    let mut is_this_the_first_file = true;
-    let mut current_run_id = 0;
-    for filename in file_iter {
-        let file_hash = match compute_the_hash(filename) {
-            Ok(file_hash) => file_hash,
-            Err(e) => {
-                // TODO what should we do here? If we have started a run, should we write it out?
-                println!("There was a problem computing a hash: {:?}", e);
-                exit(1);
+        outln!(
+            "changed bytes: {}: {} of {} chunks of {} bytes: {}",
+            display_file_name(file_name),
+            changed_chunks,
+            old_chunks.count().max(new_chunks.count()),
+            chunk_size,
+            shown.join(", ")
+        );
+    }
+    Ok(())
+}
+
+// The counts of each kind of change, followed by the changed files themselves. Missing files
+// that the ignore rules now exclude say which rule did it
+fn print_changes(diff: &EntryDiff, ignores: &IgnoreStack) {
+    outln!("Suspected corruption: {}", diff.corrupted.len());
+    outln!("Edited:    {}", diff.edited.len());
+    outln!("Modified:  {}", diff.modified.len());
+    outln!("Retargeted: {}", diff.retargeted.len());
+    outln!("Type changed: {}", diff.type_changed.len());
+    outln!("Added:     {}", diff.added.len());
+    outln!("Missing:   {}", diff.missing.len());
+    for file_name in &diff.corrupted {
+        outln!("CORRUPTED?: {}", display_file_name(file_name));
+    }
+    for file_name in &diff.edited {
+        outln!("edited:   {}", display_file_name(file_name));
+    }
+    for file_name in &diff.modified {
+        outln!("modified: {}", display_file_name(file_name));
+    }
+    for file_name in &diff.retargeted {
+        outln!("retargeted: {}", display_file_name(file_name));
+    }
+    for file_name in &diff.type_changed {
+        match diff.type_changes.get(file_name) {
+            Some((old_type, new_type)) => outln!(
+                "type changed: {} ({} -> {})",
+                display_file_name(file_name),
+                old_type.as_str(),
+                new_type.as_str()
+            ),
+            None => outln!("type changed: {}", display_file_name(file_name)),
+        }
+    }
+    for file_name in &diff.added {
+        outln!("added:    {}", display_file_name(file_name));
+    }
+    for file_name in &diff.missing {
+        match ignores.explain(Path::new(OsStr::from_bytes(file_name))) {
+            Some(rule) => outln!(
+                "missing:  {} (ignored by `{}` from {})",
+                display_file_name(file_name),
+                rule.pattern,
+                rule.source
+            ),
+            None => outln!("missing:  {}", display_file_name(file_name)),
+        }
+    }
+}
+
+// Every path seen in either of two sets of entries, sorted into exactly one of these buckets.
+// Only changes are kept by name; there are usually far fewer of them than unchanged files
+struct EntryDiff {
+    unchanged: usize,
+    // The hash changed but the size and mtime did not: nobody saved the file, the bytes just
+    // changed underneath it. This is what bitrot looks like
+    corrupted: Vec<Vec<u8>>,
+    // The hash changed along with a newer mtime, which is what saving a file looks like
+    edited: Vec<Vec<u8>>,
+    // The hash changed and the vitals don't tell us why (mtime went backwards, size changed
+    // without the mtime moving, or the old entry predates vitals)
+    modified: Vec<Vec<u8>>,
+    // A symlink that points somewhere else now
+    retargeted: Vec<Vec<u8>>,
+    // Something else is at the path now, like a symlink where there was a file
+    type_changed: Vec<Vec<u8>>,
+    // (old type, new type) for everything in type_changed
+    type_changes: BTreeMap<Vec<u8>, (EntryType, EntryType)>,
+    added: Vec<Vec<u8>>,
+    missing: Vec<Vec<u8>>,
+}
+
+impl EntryDiff {
+    fn changes_mut(&mut self) -> [&mut Vec<Vec<u8>>; 7] {
+        [
+            &mut self.corrupted,
+            &mut self.edited,
+            &mut self.modified,
+            &mut self.retargeted,
+            &mut self.type_changed,
+            &mut self.added,
+            &mut self.missing,
+        ]
+    }
+
+    // A file that couldn't be read has no entry, but it isn't missing; it gets reported as
+    // unreadable instead. Neither is anything under a directory that couldn't be read
+    fn set_aside_unreadable(&mut self, errors: &[FileError]) {
+        let unreadable: Vec<PathBuf> = errors
+            .iter()
+            .map(|error| PathBuf::from(OsStr::from_bytes(&error.file_name)))
+            .collect();
+        self.missing
+            .retain(|file_name| !under_any(file_name, &unreadable));
+    }
+
+    // Takes out every change that is the one accepted for its path, as of run_id, and says how
+    // many there were
+    fn set_aside_accepted(&mut self, db: &Connection, run_id: i64) -> Result<usize, sqlite::Error> {
+        let mut acceptances = Acceptances::new(db)?;
+        let mut observed = EntryLookup::new(db, run_id)?;
+        let mut accepted = 0;
+        for changed in self.changes_mut() {
+            let mut kept = Vec::with_capacity(changed.len());
+            for file_name in changed.drain(..) {
+                let entry = observed.get(&file_name)?;
+                if acceptances.is_accepted(&file_name, entry.as_ref())? {
+                    accepted += 1;
+                } else {
+                    kept.push(file_name);
+                }
+            }
+            *changed = kept;
+        }
+        Ok(accepted)
+    }
+
+    fn has_changes(&self) -> bool {
+        !(self.corrupted.is_empty()
+            && self.edited.is_empty()
+            && self.modified.is_empty()
+            && self.retargeted.is_empty()
+            && self.type_changed.is_empty()
+            && self.added.is_empty()
+            && self.missing.is_empty())
+    }
+
+    // Which bucket a path that is on both sides goes in
+    fn sort_change(&mut self, file_name: &[u8], old_entry: &FileEntry, new_entry: &FileEntry) {
+        if old_entry.same_contents(new_entry) {
+            self.unchanged += 1;
+            return;
+        }
+        if old_entry.entry_type != new_entry.entry_type {
+            self.type_changed.push(file_name.to_vec());
+            self.type_changes.insert(
+                file_name.to_vec(),
+                (old_entry.entry_type, new_entry.entry_type),
+            );
+            return;
+        }
+        match new_entry.entry_type {
+            EntryType::File => (),
+            EntryType::Symlink => {
+                self.retargeted.push(file_name.to_vec());
+                return;
             }
+            // A device node standing for a different device; there are no contents to judge
+            // by the vitals
+            _ => {
+                self.modified.push(file_name.to_vec());
+                return;
+            }
+        }
+        match (&old_entry.vitals, &new_entry.vitals) {
+            (Some(old_vitals), Some(new_vitals))
+                if old_vitals.size == new_vitals.size
+                    && old_vitals.mtime() == new_vitals.mtime() =>
+            {
+                self.corrupted.push(file_name.to_vec())
+            }
+            (Some(old_vitals), Some(new_vitals)) if new_vitals.mtime() > old_vitals.mtime() => {
+                self.edited.push(file_name.to_vec())
+            }
+            _ => self.modified.push(file_name.to_vec()),
+        }
+    }
+}
+
+// Compares two sets of entries, each sorted by path, by walking them side by side, so that
+// neither has to be held whole; an EntryCursor on each side reads them straight from the DB
+fn diff_entries<N, E>(
+    old: impl Iterator<Item = Result<(N, E), sqlite::Error>>,
+    new: impl Iterator<Item = Result<(N, E), sqlite::Error>>,
+) -> Result<EntryDiff, sqlite::Error>
+where
+    N: AsRef<[u8]>,
+    E: Borrow<FileEntry>,
+{
+    let mut diff = EntryDiff {
+        unchanged: 0,
+        corrupted: Vec::new(),
+        edited: Vec::new(),
+        modified: Vec::new(),
+        retargeted: Vec::new(),
+        type_changed: Vec::new(),
+        type_changes: BTreeMap::new(),
+        added: Vec::new(),
+        missing: Vec::new(),
+    };
+    let (mut old, mut new) = (old.fuse(), new.fuse());
+    let mut old_next = old.next().transpose()?;
+    let mut new_next = new.next().transpose()?;
+    loop {
+        let order = match (&old_next, &new_next) {
+            (None, None) => break,
+            (Some(_), None) => cmp::Ordering::Less,
+            (None, Some(_)) => cmp::Ordering::Greater,
+            (Some((old_name, _)), Some((new_name, _))) => old_name.as_ref().cmp(new_name.as_ref()),
         };
-        if is_this_the_first_file {
-            is_this_the_first_file = false;
-            match db.execute("INSERT INTO run (start_time) VALUES (CURRENT_TIMESTAMP);") {
-                Ok(_) => (), // TODO use the function/map that does this prettier
-                Err(e) => {
-                    // TODO what should we do here? Maybe we should
-                    //      have a scheme where we copy the DB, write to
-                    //      the copy, and then move it back into place on
-                    //      success only
-                    println!("There was a problem computing a hash: {:?}", e);
-                    exit(1);
+        match order {
+            cmp::Ordering::Less => {
+                if let Some((file_name, _)) = old_next {
+                    diff.missing.push(file_name.as_ref().to_vec());
                 }
-            };
-            let mut statement = db
-                .prepare("SELECT id FROM run ORDER BY id DESC LIMIT 1;")
-                .unwrap();
-            while State::Row == statement.next().unwrap() {
-                current_run_id = statement.read::<i64>(0).unwrap();
+                old_next = old.next().transpose()?;
+            }
+            cmp::Ordering::Greater => {
+                if let Some((file_name, _)) = new_next {
+                    diff.added.push(file_name.as_ref().to_vec());
+                }
+                new_next = new.next().transpose()?;
+            }
+            cmp::Ordering::Equal => {
+                if let (Some((file_name, old_entry)), Some((_, new_entry))) = (old_next, new_next) {
+                    diff.sort_change(file_name.as_ref(), old_entry.borrow(), new_entry.borrow());
+                }
+                old_next = old.next().transpose()?;
+                new_next = new.next().transpose()?;
             }
         }
+    }
+    Ok(diff)
+}
 
-        let mut statement = db
-            .prepare(
-                "\
-                        INSERT INTO file_entry (run_id, file_name, file_hash) VALUES (?, ?, ?);\
-                    ",
-            )
-            .unwrap();
+// What a run recorded about one file
+#[derive(Clone)]
+struct FileEntry {
+    entry_type: EntryType,
+    // Empty for anything but regular files
+    file_hash: String,
+    // What file_hash was taken with
+    hash_algorithm: HashAlgorithm,
+    // Any other digests taken in the same read
+    extra_digests: Vec<(HashAlgorithm, String)>,
+    // None for entries recorded before frzr kept vitals
+    vitals: Option<Vitals>,
+    // Where a symlink points, when links are recorded rather than followed
+    link_target: Option<Vec<u8>>,
+    // The chunk size of the chunk digests taken along with file_hash, if any were
+    chunk_size: Option<u64>,
+}
 
-        statement.bind(1, current_run_id).unwrap();
-        // TODO: Why does ? not work, and I have to use unwrap() instead? The error was:
-        //       Cannot use the `?` operator in a function that returns `()`
-        let filename_bytes = filename.as_os_str().as_bytes();
-        statement.bind(2, filename_bytes).unwrap();
-        statement.bind(3, file_hash.as_bytes()).unwrap();
-        statement.next().unwrap();
-        println!("filename: {:?}", filename);
+// The columns FileEntry::read expects, from file_entry LEFT JOINed with file_chunks and
+// followed by VITALS_COLUMNS
+const ENTRY_COLUMNS: &str = "file_entry.digest, link_target, file_type, file_entry.algorithm, \
+    chunk_size";
+
+impl FileEntry {
+    // Reads ENTRY_COLUMNS and VITALS_COLUMNS starting at first_index. Every column is NULL
+    // for a baseline entry whose file_entry is gone, which makes it a file with no digest
+    fn read(statement: &Statement, first_index: usize) -> Result<FileEntry, sqlite::Error> {
+        Ok(FileEntry {
+            file_hash: digest_to_hex(
+                &statement
+                    .read::<Option<Vec<u8>>>(first_index)?
+                    .unwrap_or_default(),
+            ),
+            link_target: statement.read::<Option<Vec<u8>>>(first_index + 1)?,
+            entry_type: EntryType::from_str(
+                &statement
+                    .read::<Option<String>>(first_index + 2)?
+                    .unwrap_or_default(),
+            ),
+            hash_algorithm: statement
+                .read::<Option<i64>>(first_index + 3)?
+                .and_then(HashAlgorithm::from_id)
+                .unwrap_or(DEFAULT_ALGORITHM),
+            extra_digests: Vec::new(),
+            chunk_size: statement
+                .read::<Option<i64>>(first_index + 4)?
+                .map(|chunk_size| chunk_size as u64),
+            vitals: Vitals::read(statement, first_index + 5)?,
+        })
     }
-    // If we haven't crashed yet, then the run exists in the DB, the file_entry rows exist in
-    // the db, and the run can be marked finished
-    let mut statement = db
-        .prepare(
-            "\
-                    UPDATE run set end_time = CURRENT_TIMESTAMP WHERE id = ?;\
-                ",
-        )
-        .unwrap();
-    statement.bind(1, current_run_id).unwrap();
-    statement.next().unwrap();
-    println!("Inserting the end_time timestamp");
-    println!("Reached the end of the check() function");
-}
 
-fn compute_the_hash(file: &PathBuf) -> Result<String, io::Error> {
-    let mut the_file = fs::File::open(file)?;
+    // Whether recording this entry again would say nothing new about the path. Anything at
+    // all that differs, down to the ctime or a digest taken with another algorithm, does
+    fn same_record(&self, other: &FileEntry) -> bool {
+        let mut mine = self.extra_digests.clone();
+        let mut theirs = other.extra_digests.clone();
+        mine.sort();
+        theirs.sort();
+        self.entry_type == other.entry_type
+            && self.hash_algorithm == other.hash_algorithm
+            && self.file_hash == other.file_hash
+            && mine == theirs
+            && self.vitals == other.vitals
+            && self.link_target == other.link_target
+            && self.chunk_size == other.chunk_size
+    }
 
-    let mut hasher = Sha256::new();
+    // Whether two entries describe the same contents: the same kind of thing, with the same
+    // hash, link target or device number
+    fn same_contents(&self, other: &FileEntry) -> bool {
+        self.entry_type == other.entry_type
+            && self.same_digest(other)
+            && self.link_target == other.link_target
+            && (!self.entry_type.is_device() || self.rdev() == other.rdev())
+    }
 
-    let mut buf: [u8; 4096] = [0; 4096]; // Read 4k at a time
-    loop {
-        let num_bytes_read = the_file.read(&mut buf)?;
-        if num_bytes_read == 0 {
-            break;
+    // Digests can only be compared when they were taken with the same algorithm. `check` makes
+    // sure to take the baseline's, so there is always one in common with the baseline
+    fn same_digest(&self, other: &FileEntry) -> bool {
+        let common = std::iter::once(self.hash_algorithm)
+            .chain(self.extra_digests.iter().map(|(algorithm, _)| *algorithm))
+            .find_map(|algorithm| Some((self.digest(algorithm)?, other.digest(algorithm)?)));
+        match common {
+            Some((mine, theirs)) => mine == theirs,
+            None => self.file_hash == other.file_hash,
         }
-        // read bytes from the file, pass them to the hasher:
-        hasher.update(&buf[..num_bytes_read]);
     }
 
-    let result: String = format!("{:x}", hasher.finalize());
-    Ok(result)
-}
+    fn digest(&self, algorithm: HashAlgorithm) -> Option<&str> {
+        if self.hash_algorithm == algorithm {
+            return Some(&self.file_hash);
+        }
+        self.extra_digests
+            .iter()
+            .find(|(extra_algorithm, _)| *extra_algorithm == algorithm)
+            .map(|(_, digest)| digest.as_str())
+    }
 
-fn give_me_the_files(
-    path_string: PathBuf,
-    ignore_paths: Vec<PathBuf>,
-) -> Result<Vec<PathBuf>, io::Error> {
-    let mut all_the_files: Vec<PathBuf> = Vec::new();
-    let visited_dirs: Vec<DirEntry> = Vec::new();
-    let result = dir_walk_recurser(path_string, visited_dirs, &mut all_the_files, &ignore_paths);
-    match result {
-        Ok(_) => Ok(all_the_files),
-        Err(e) => Err(e),
+    fn rdev(&self) -> Option<i64> {
+        self.vitals.as_ref().map(|vitals| vitals.rdev)
     }
 }
 
-// TODO: is visited_dirs actually doing anything?
-fn dir_walk_recurser(
-    path_string: PathBuf,
-    mut visited_dirs: Vec<DirEntry>,
-    out_files: &mut Vec<PathBuf>,
-    ignore_paths: &Vec<PathBuf>,
-) -> Result<Vec<DirEntry>, io::Error> {
-    // Return early if you see the .frzr/ directory:
-    if ignore_paths.contains(&path_string) {
-        println!("Skipping ignored path: {:?}", path_string);
-        return Ok(visited_dirs);
+// What kind of thing a path is. Only regular files are ever opened; reading a FIFO would block
+// until something writes to it, and reading a device could go on forever
+#[derive(Clone, Copy, PartialEq)]
+enum EntryType {
+    File,
+    Symlink,
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+impl EntryType {
+    // None for directories, which are walked rather than recorded
+    fn from_file_type(file_type: &fs::FileType) -> Option<EntryType> {
+        if file_type.is_file() {
+            Some(EntryType::File)
+        } else if file_type.is_symlink() {
+            Some(EntryType::Symlink)
+        } else if file_type.is_fifo() {
+            Some(EntryType::Fifo)
+        } else if file_type.is_socket() {
+            Some(EntryType::Socket)
+        } else if file_type.is_char_device() {
+            Some(EntryType::CharDevice)
+        } else if file_type.is_block_device() {
+            Some(EntryType::BlockDevice)
+        } else {
+            None
+        }
     }
-    let dir_iter = match fs::read_dir(path_string) {
-        Ok(rd) => rd,
-        Err(e) => {
-            println!("An error occurred: {}", e);
-            return Err(e);
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            EntryType::File => "file",
+            EntryType::Symlink => "symlink",
+            EntryType::Fifo => "fifo",
+            EntryType::Socket => "socket",
+            EntryType::CharDevice => "char",
+            EntryType::BlockDevice => "block",
         }
-    };
-    for entry in dir_iter {
-        let entry = match entry {
-            Ok(de) => de,
-            Err(e) => {
-                println!("An error occurred: {}", e);
-                return Err(e);
-            }
-        };
-        // println!("{:?}", entry);
-        let file_type = match entry.file_type() {
-            Ok(ft) => ft,
-            Err(e) => {
-                println!("An error occurred: {}", e);
-                return Err(e);
-            }
+    }
+
+    // Unknown types are treated as files, like entries from before types were recorded
+    fn from_str(entry_type: &str) -> EntryType {
+        match entry_type {
+            "symlink" => EntryType::Symlink,
+            "fifo" => EntryType::Fifo,
+            "socket" => EntryType::Socket,
+            "char" => EntryType::CharDevice,
+            "block" => EntryType::BlockDevice,
+            _ => EntryType::File,
+        }
+    }
+
+    fn is_device(&self) -> bool {
+        matches!(self, EntryType::CharDevice | EntryType::BlockDevice)
+    }
+}
+
+// The stat(2) fields recorded next to each hash
+#[derive(Clone, PartialEq)]
+struct Vitals {
+    size: i64,
+    mtime: i64,
+    mtime_nsec: i64,
+    ctime: i64,
+    ctime_nsec: i64,
+    inode: i64,
+    device: i64,
+    mode: i64,
+    // Which device a device node stands for; 0 for everything else
+    rdev: i64,
+}
+
+// The columns of file_entry holding Vitals, in the order read_vitals expects them
+const VITALS_COLUMNS: &str =
+    "size, mtime, mtime_nsec, ctime, ctime_nsec, inode, device, mode, rdev";
+
+impl Vitals {
+    fn from_metadata(metadata: &fs::Metadata) -> Vitals {
+        // SQLite only has signed 64-bit integers; inode and device numbers are stored as their
+        // bit patterns
+        Vitals {
+            size: metadata.size() as i64,
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+            inode: metadata.ino() as i64,
+            device: metadata.dev() as i64,
+            mode: metadata.mode() as i64,
+            rdev: metadata.rdev() as i64,
+        }
+    }
+
+    // Whether a file looks untouched since these vitals were taken, going by stat alone. The
+    // ctime is included because anything that rewrites a file through the filesystem bumps it,
+    // even when the mtime is deliberately set back
+    fn is_unchanged(&self, now: &Vitals) -> bool {
+        self.size == now.size
+            && self.mtime() == now.mtime()
+            && (self.ctime, self.ctime_nsec) == (now.ctime, now.ctime_nsec)
+            && self.inode == now.inode
+            && self.device == now.device
+    }
+
+    fn mtime(&self) -> (i64, i64) {
+        (self.mtime, self.mtime_nsec)
+    }
+
+    fn bind(&self, statement: &mut Statement, first_index: usize) -> Result<(), sqlite::Error> {
+        statement.bind(first_index, self.size)?;
+        statement.bind(first_index + 1, self.mtime)?;
+        statement.bind(first_index + 2, self.mtime_nsec)?;
+        statement.bind(first_index + 3, self.ctime)?;
+        statement.bind(first_index + 4, self.ctime_nsec)?;
+        statement.bind(first_index + 5, self.inode)?;
+        statement.bind(first_index + 6, self.device)?;
+        statement.bind(first_index + 7, self.mode)?;
+        statement.bind(first_index + 8, self.rdev)
+    }
+
+    // Reads VITALS_COLUMNS starting at first_index; None if they are NULL
+    fn read(statement: &Statement, first_index: usize) -> Result<Option<Vitals>, sqlite::Error> {
+        let size = match statement.read::<Option<i64>>(first_index)? {
+            Some(size) => size,
+            None => return Ok(None),
         };
-        let path_name = entry.path();
-        if file_type.is_dir() {
-            // add this to the list and recurse
-            visited_dirs.push(entry);
-            visited_dirs = match dir_walk_recurser(path_name, visited_dirs, out_files, ignore_paths)
-            {
-                Ok(vd) => vd,
-                Err(e) => {
-                    println!("An error occurred: {}", e);
-                    return Err(e);
-                }
-            };
+        Ok(Some(Vitals {
+            size,
+            mtime: statement.read::<i64>(first_index + 1)?,
+            mtime_nsec: statement.read::<i64>(first_index + 2)?,
+            ctime: statement.read::<i64>(first_index + 3)?,
+            ctime_nsec: statement.read::<i64>(first_index + 4)?,
+            inode: statement.read::<i64>(first_index + 5)?,
+            device: statement.read::<i64>(first_index + 6)?,
+            mode: statement.read::<i64>(first_index + 7)?,
+            rdev: statement.read::<Option<i64>>(first_index + 8)?.unwrap_or(0),
+        }))
+    }
+}
+
+// The most recent run that got to the end, if there is one
+fn latest_finished_run(db: &Connection) -> Result<Option<i64>, sqlite::Error> {
+    let mut statement =
+        db.prepare("SELECT id FROM run WHERE end_time IS NOT NULL ORDER BY id DESC LIMIT 1;")?;
+    match statement.next()? {
+        State::Row => Ok(Some(statement.read::<i64>(0)?)),
+        State::Done => Ok(None),
+    }
+}
+
+// What run ?1 saw: for each path, the id of its newest file_entry from run ?1 itself or any
+// finished run before it. A run only records what changed, so a path it found unchanged has its
+// entry from an earlier run; a path it didn't find has a 'gone' entry. SQLite takes id from the
+// row that has the MAX(run_id)
+const RUN_STATE: &str = "SELECT id, MAX(run_id) FROM file_entry \
+    WHERE run_id <= ?1 AND (run_id = ?1 OR run_id IN (SELECT id FROM run WHERE status = 'finished')) \
+    GROUP BY path_id";
+
+// The same for the one path named ?2, for when going through every path would be a waste
+const PATH_STATE: &str = "SELECT id FROM file_entry \
+    WHERE path_id = (SELECT id FROM path WHERE name = ?2) AND run_id <= ?1 \
+        AND (run_id = ?1 OR run_id IN (SELECT id FROM run WHERE status = 'finished')) \
+    ORDER BY run_id DESC LIMIT 1";
+
+// Which of file_entry, JOINed with path, are in what run ?1 saw, or with only_seen, in what
+// run ?1 itself found. The two only differ for an unfinished run, which hasn't got to every
+// path yet
+fn run_entries_filter(only_seen: bool) -> String {
+    format!(
+        "file_entry.id IN (SELECT id FROM ({})) AND file_type != 'gone'{}",
+        RUN_STATE,
+        if only_seen {
+            " AND last_seen_run = ?1"
         } else {
-            println!("File:\t{:?}", path_name);
-            out_files.push(path_name);
+            ""
         }
-    }
-    Ok(visited_dirs)
+    )
 }
 
-fn open_and_initialize_db() -> Result<Connection, sqlite::Error> {
-    // TODO: do I need the opening "./" on these path strings? (check everywhere, not just here)
-    let connection = sqlite::open("./.frzr/frzr.db")?;
-    // If you need to test what happens when this function returns an error, uncomment this:
-    // connection.execute("CREATE TABLE CREATE TABLE")?;
-    connection.execute(
-        "
-        CREATE TABLE IF NOT EXISTS schema_version (id INTEGER PRIMARY KEY ASC, version INTEGER);
-        ",
-    )?;
+// How many entries EntryCursor::run would go through, and the bytes their files add up to
+fn count_entries(
+    db: &Connection,
+    run_id: i64,
+    only_seen: bool,
+) -> Result<(u64, u64), sqlite::Error> {
+    let mut statement = db.prepare(format!(
+        "SELECT COUNT(*), IFNULL(SUM(size), 0) FROM file_entry \
+            JOIN path ON path.id = file_entry.path_id WHERE {};",
+        run_entries_filter(only_seen)
+    ))?;
+    statement.bind(1, run_id)?;
+    statement.next()?;
+    Ok((
+        statement.read::<i64>(0)? as u64,
+        statement.read::<i64>(1)? as u64,
+    ))
+}
 
-    let mut latest_version_in_db = 0;
-    {
-        // TODO Why is this scope necessary?
-        let mut statement =
-            connection.prepare("SELECT * FROM schema_version ORDER BY version DESC LIMIT 1;")?;
-        while let State::Row = statement.next()? {
-            latest_version_in_db = statement.read::<i64>(1)?;
-        }
-    }
-    if latest_version_in_db == 0 {
-        // Run may grow to include other statistics about the run, like number of files processed
-        connection.execute(
-            "
-            CREATE TABLE IF NOT EXISTS run (
-                id INTEGER PRIMARY KEY ASC NOT NULL,
-                start_time datetime NOT NULL,
-                end_time datetime
-                );
-            ",
-        )?;
-
-        // I'm trying the filename as BLOB instead of string because, at least for Linux,
-        // not all valid paths are strings in any single encoding
-        // file_name in this case means "relative path to the file, including the filename"
-
-        // Instead of using time, datetime, or date for file_entry, I think I should do run_id
-        // A run should be inserted at the top of any checksum calculation, should have a start
-        // and an end, and then can be referenced from file_entry. This might be useful
-
-        // Should file_hash be a blob, also? Probably easier to to select on if it is a string
-        // That raises the question for file_name, too. Not sure about types here
-        connection.execute(
-            "
-            CREATE TABLE IF NOT EXISTS file_entry (
-                id INTEGER PRIMARY KEY ASC NOT NULL,
-                file_name BLOB,
-                file_hash STRING,
-                run_id INTEGER NOT NULL,
-                FOREIGN KEY(run_id) REFERENCES run(id)
-                );
-            ",
-        )?;
-        latest_version_in_db = 1;
-        let mut statement =
-            connection.prepare("INSERT INTO schema_version (version) VALUES (?);")?;
-        statement.bind(1, latest_version_in_db)?;
-        statement.next()?;
+// Entries a row at a time, in path order, for going through every path without holding them
+// all. SQLite orders BLOBs byte by byte, the same as Vec<u8> does
+struct EntryCursor<'db> {
+    // Selects path.name and a file_entry id, followed by ENTRY_COLUMNS and VITALS_COLUMNS
+    statement: Statement<'db>,
+    digest_statement: Statement<'db>,
+    // Stepping a statement that is done would start it over
+    done: bool,
+}
+
+impl<'db> EntryCursor<'db> {
+    // What run_id saw, or with only_seen, what it found itself; see run_entries_filter
+    fn run(
+        db: &'db Connection,
+        run_id: i64,
+        only_seen: bool,
+    ) -> Result<EntryCursor<'db>, sqlite::Error> {
+        let mut statement = db.prepare(format!(
+            "SELECT path.name, file_entry.id, {}, {} FROM file_entry \
+                JOIN path ON path.id = file_entry.path_id \
+                LEFT JOIN file_chunks ON file_chunks.file_entry_id = file_entry.id \
+                WHERE {} ORDER BY path.name;",
+            ENTRY_COLUMNS,
+            VITALS_COLUMNS,
+            run_entries_filter(only_seen)
+        ))?;
+        statement.bind(1, run_id)?;
+        EntryCursor::new(db, statement)
+    }
+
+    // The baseline's entries. The vitals come from the file_entry each hash was accepted from
+    fn baseline(db: &'db Connection) -> Result<EntryCursor<'db>, sqlite::Error> {
+        let statement = db.prepare(format!(
+            "SELECT path.name, baseline.file_entry_id, {}, {} FROM baseline \
+                JOIN path ON path.id = baseline.path_id \
+                LEFT JOIN file_entry ON file_entry.id = baseline.file_entry_id \
+                LEFT JOIN file_chunks ON file_chunks.file_entry_id = file_entry.id \
+                ORDER BY path.name;",
+            ENTRY_COLUMNS, VITALS_COLUMNS
+        ))?;
+        EntryCursor::new(db, statement)
+    }
+
+    fn new(
+        db: &'db Connection,
+        statement: Statement<'db>,
+    ) -> Result<EntryCursor<'db>, sqlite::Error> {
+        Ok(EntryCursor {
+            statement,
+            digest_statement: db.prepare(EXTRA_DIGESTS)?,
+            done: false,
+        })
     }
-    Ok(connection)
+
+    fn read(&mut self) -> Result<(Vec<u8>, FileEntry), sqlite::Error> {
+        let file_name = self.statement.read::<Vec<u8>>(0)?;
+        let mut entry = FileEntry::read(&self.statement, 2)?;
+        let file_entry_id = self.statement.read::<i64>(1)?;
+        read_extra_digests(&mut self.digest_statement, file_entry_id, &mut entry)?;
+        Ok((file_name, entry))
+    }
+}
+
+impl Iterator for EntryCursor<'_> {
+    type Item = Result<(Vec<u8>, FileEntry), sqlite::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.statement.next() {
+            Ok(State::Row) => Some(self.read()),
+            Ok(State::Done) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// One path's entry as of a run, or in the baseline, looked up as each path comes along rather
+// than loading them all up front
+struct EntryLookup<'db> {
+    // None for the baseline
+    run_id: Option<i64>,
+    // Selects a file_entry id, followed by ENTRY_COLUMNS and VITALS_COLUMNS, for the path ?2
+    statement: Statement<'db>,
+    digest_statement: Statement<'db>,
+}
+
+impl<'db> EntryLookup<'db> {
+    fn new(db: &'db Connection, run_id: i64) -> Result<EntryLookup<'db>, sqlite::Error> {
+        let statement = db.prepare(format!(
+            "SELECT file_entry.id, {}, {} FROM file_entry \
+                LEFT JOIN file_chunks ON file_chunks.file_entry_id = file_entry.id \
+                WHERE file_entry.id = ({}) AND file_type != 'gone';",
+            ENTRY_COLUMNS, VITALS_COLUMNS, PATH_STATE
+        ))?;
+        EntryLookup::with(db, Some(run_id), statement)
+    }
+
+    // Like EntryCursor::baseline, the vitals come from the file_entry the hash was accepted from
+    fn baseline(db: &'db Connection) -> Result<EntryLookup<'db>, sqlite::Error> {
+        let statement = db.prepare(format!(
+            "SELECT baseline.file_entry_id, {}, {} FROM baseline \
+                LEFT JOIN file_entry ON file_entry.id = baseline.file_entry_id \
+                LEFT JOIN file_chunks ON file_chunks.file_entry_id = file_entry.id \
+                WHERE baseline.path_id = (SELECT id FROM path WHERE name = ?2);",
+            ENTRY_COLUMNS, VITALS_COLUMNS
+        ))?;
+        EntryLookup::with(db, None, statement)
+    }
+
+    fn with(
+        db: &'db Connection,
+        run_id: Option<i64>,
+        statement: Statement<'db>,
+    ) -> Result<EntryLookup<'db>, sqlite::Error> {
+        Ok(EntryLookup {
+            run_id,
+            statement,
+            digest_statement: db.prepare(EXTRA_DIGESTS)?,
+        })
+    }
+
+    fn get(&mut self, file_name: &[u8]) -> Result<Option<FileEntry>, sqlite::Error> {
+        self.statement.reset()?;
+        if let Some(run_id) = self.run_id {
+            self.statement.bind(1, run_id)?;
+        }
+        self.statement.bind(2, file_name)?;
+        let entry = match self.statement.next()? {
+            State::Row => {
+                let mut entry = FileEntry::read(&self.statement, 1)?;
+                let file_entry_id = self.statement.read::<i64>(0)?;
+                read_extra_digests(&mut self.digest_statement, file_entry_id, &mut entry)?;
+                Some(entry)
+            }
+            State::Done => None,
+        };
+        // Left stepping, the statement would keep a read transaction open, and the WAL
+        // couldn't be checkpointed past it
+        self.statement.reset()?;
+        Ok(entry)
+    }
+}
+
+const EXTRA_DIGESTS: &str = "SELECT algorithm, digest FROM file_digest WHERE file_entry_id = ?;";
+
+// Adds the other digests taken along with file_entry_id to its entry, using a statement
+// prepared from EXTRA_DIGESTS. Algorithms this version doesn't know are left out
+fn read_extra_digests(
+    statement: &mut Statement,
+    file_entry_id: i64,
+    entry: &mut FileEntry,
+) -> Result<(), sqlite::Error> {
+    statement.reset()?;
+    statement.bind(1, file_entry_id)?;
+    while let State::Row = statement.next()? {
+        if let Some(algorithm) = HashAlgorithm::from_id(statement.read::<i64>(0)?) {
+            let digest = digest_to_hex(&statement.read::<Vec<u8>>(1)?);
+            entry.extra_digests.push((algorithm, digest));
+        }
+    }
+    Ok(())
+}
+
+// What a run's file_hash values were taken with
+fn run_hash_algorithm(db: &Connection, run_id: i64) -> Result<HashAlgorithm, sqlite::Error> {
+    let mut statement = db.prepare("SELECT hash_algorithm FROM run WHERE id = ?;")?;
+    statement.bind(1, run_id)?;
+    let mut algorithm = DEFAULT_ALGORITHM;
+    if let State::Row = statement.next()? {
+        algorithm = HashAlgorithm::from_str(&statement.read::<String>(0)?).unwrap_or(algorithm);
+    }
+    Ok(algorithm)
+}
+
+// The algorithms a run hashing with primary has to take as well, to have a digest in common
+// with every file in the baseline: whatever the files without a primary digest were hashed with
+fn baseline_hash_algorithms(
+    db: &Connection,
+    primary: HashAlgorithm,
+) -> Result<Vec<HashAlgorithm>, sqlite::Error> {
+    let mut algorithms = Vec::new();
+    let mut statement = db.prepare(
+        "SELECT DISTINCT file_entry.algorithm FROM baseline \
+            JOIN file_entry ON file_entry.id = baseline.file_entry_id \
+            WHERE file_type = 'file' AND file_entry.algorithm != ?1 AND NOT EXISTS ( \
+                SELECT 1 FROM file_digest \
+                    WHERE file_entry_id = baseline.file_entry_id AND algorithm = ?1);",
+    )?;
+    statement.bind(1, primary.id())?;
+    while let State::Row = statement.next()? {
+        if let Some(algorithm) = HashAlgorithm::from_id(statement.read::<i64>(0)?) {
+            algorithms.push(algorithm);
+        }
+    }
+    Ok(algorithms)
+}
+
+// The known-good hashes, as promoted from observed runs by `frzr resolve`, a path at a time
+struct Acceptances<'db> {
+    baseline: EntryLookup<'db>,
+    // Whether a path has ever been resolved, so that a file missing from the baseline can be
+    // told apart from one that nobody has looked at
+    resolved: Statement<'db>,
+}
+
+impl<'db> Acceptances<'db> {
+    fn new(db: &'db Connection) -> Result<Acceptances<'db>, sqlite::Error> {
+        Ok(Acceptances {
+            baseline: EntryLookup::baseline(db)?,
+            resolved: db
+                .prepare("SELECT EXISTS (SELECT 1 FROM resolution WHERE file_name = ?);")?,
+        })
+    }
+
+    // Whether the observed state of a file (None if it is gone) is the one that was accepted
+    fn is_accepted(
+        &mut self,
+        file_name: &[u8],
+        observed: Option<&FileEntry>,
+    ) -> Result<bool, sqlite::Error> {
+        self.resolved.reset()?;
+        self.resolved.bind(1, file_name)?;
+        self.resolved.next()?;
+        let resolved = self.resolved.read::<i64>(0)? != 0;
+        self.resolved.reset()?;
+        if !resolved {
+            return Ok(false);
+        }
+        Ok(match (self.baseline.get(file_name)?, observed) {
+            (Some(accepted), Some(observed)) => accepted.same_contents(observed),
+            (None, None) => true,
+            _ => false,
+        })
+    }
+}
+
+// Whether anything has ever been accepted; until then, there is no baseline to check against
+fn baseline_established(db: &Connection) -> Result<bool, sqlite::Error> {
+    let mut statement = db.prepare("SELECT EXISTS (SELECT 1 FROM resolution);")?;
+    statement.next()?;
+    Ok(statement.read::<i64>(0)? != 0)
+}
+
+// Accepts everything run_id found, the way accept_change would one file at a time, for the
+// first run to establish the baseline with. Anything but a file has no digest to log
+fn seed_baseline(db: &Connection, run_id: i64) -> Result<(), sqlite::Error> {
+    let seen = run_entries_filter(true);
+    db.execute("BEGIN;")?;
+    for query in [
+        format!(
+            "INSERT INTO resolution \
+                (file_name, old_hash, new_hash, run_id, accepted_by, accepted_at) \
+                SELECT path.name, NULL, \
+                    CASE WHEN file_entry.digest IS NULL THEN NULL \
+                        ELSE lower(hex(file_entry.digest)) END, \
+                    ?1, ?2, CURRENT_TIMESTAMP \
+                FROM file_entry JOIN path ON path.id = file_entry.path_id WHERE {} \
+                ORDER BY path.name;",
+            seen
+        ),
+        format!(
+            "INSERT OR REPLACE INTO baseline (path_id, file_entry_id, accepted_by, accepted_at) \
+                SELECT path_id, file_entry.id, ?2, CURRENT_TIMESTAMP \
+                FROM file_entry JOIN path ON path.id = file_entry.path_id WHERE {};",
+            seen
+        ),
+    ] {
+        let mut statement = db.prepare(query)?;
+        statement.bind(1, run_id)?;
+        statement.bind(2, "frzr check (first run)")?;
+        statement.next()?;
+    }
+    db.execute("COMMIT;")
+}
+
+fn resolve(accept_paths: Vec<String>, accept_all: bool, accepted_by: String) {
+    let db = open_db_to_write("resolve", false);
+    let mut latest_run_id = 0;
+    let mut statement = db
+        .prepare("SELECT id FROM run WHERE end_time IS NOT NULL ORDER BY id DESC LIMIT 1;")
+        .unwrap();
+    while State::Row == statement.next().unwrap() {
+        latest_run_id = statement.read::<i64>(0).unwrap();
+    }
+    if latest_run_id == 0 {
+        outln!("No finished runs in the DB yet; run `frzr check` first");
+        return;
+    }
+    let diff = EntryCursor::baseline(&db)
+        .and_then(|baseline| diff_entries(baseline, EntryCursor::run(&db, latest_run_id, false)?));
+    let diff = match diff {
+        Ok(diff) => diff,
+        Err(e) => {
+            outln!(
+                "There was a problem comparing against the baseline: {:?}",
+                e
+            );
+            exit(EXIT_ERROR);
+        }
+    };
+    let mut pending: Vec<(&str, Vec<u8>)> = Vec::new();
+    pending.extend(diff.corrupted.into_iter().map(|f| ("CORRUPTED?", f)));
+    pending.extend(diff.edited.into_iter().map(|f| ("edited", f)));
+    pending.extend(diff.modified.into_iter().map(|f| ("modified", f)));
+    pending.extend(diff.retargeted.into_iter().map(|f| ("retargeted", f)));
+    pending.extend(diff.type_changed.into_iter().map(|f| ("type changed", f)));
+    pending.extend(diff.added.into_iter().map(|f| ("added", f)));
+    pending.extend(diff.missing.into_iter().map(|f| ("missing", f)));
+    if pending.is_empty() {
+        outln!(
+            "Nothing to resolve; run {} matches the baseline",
+            latest_run_id
+        );
+        return;
+    }
+
+    let lookups = EntryLookup::baseline(&db)
+        .and_then(|baseline| Ok((baseline, EntryLookup::new(&db, latest_run_id)?)));
+    let (mut baseline, mut latest) = match lookups {
+        Ok(lookups) => lookups,
+        Err(e) => {
+            outln!("There was a problem reading the baseline: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    let accept_prefixes = path_prefixes(&accept_paths);
+    let interactive = !accept_all && accept_prefixes.is_empty();
+    let mut accept_rest = false;
+    let mut accepted_count = 0;
+    for (kind, file_name) in &pending {
+        let path = Path::new(OsStr::from_bytes(file_name));
+        let accept = if accept_all || accept_rest {
+            true
+        } else if !interactive {
+            accept_prefixes
+                .iter()
+                .any(|prefix| path.starts_with(prefix))
+        } else {
+            outln!("{}: {}", kind, display_file_name(file_name));
+            match prompt("Accept? [y]es, [n]o, [a]ll remaining, [q]uit: ") {
+                Some(answer) if answer == "y" => true,
+                Some(answer) if answer == "a" => {
+                    accept_rest = true;
+                    true
+                }
+                Some(answer) if answer == "q" => break,
+                Some(_) => false,
+                None => break, // stdin closed
+            }
+        };
+        if !accept {
+            continue;
+        }
+        let accepted = db.execute("BEGIN;").and_then(|_| {
+            let old_entry = baseline.get(file_name)?;
+            let new_entry = latest.get(file_name)?;
+            accept_change(
+                &db,
+                latest_run_id,
+                file_name,
+                old_entry.as_ref().map(|entry| &entry.file_hash),
+                new_entry.as_ref().map(|entry| &entry.file_hash),
+                &accepted_by,
+            )?;
+            db.execute("COMMIT;")
+        });
+        match accepted {
+            Ok(_) => accepted_count += 1,
+            Err(e) => {
+                outln!(
+                    "There was a problem accepting {}: {:?}",
+                    display_file_name(file_name),
+                    e
+                );
+                exit(EXIT_ERROR);
+            }
+        }
+    }
+    outln!(
+        "Accepted {} of {} pending changes as {}",
+        accepted_count,
+        pending.len(),
+        accepted_by
+    );
+}
+
+// Makes the observed state of one file (None if it went missing) the baseline, and logs who did
+// it. Callers are expected to wrap this in a transaction
+fn accept_change(
+    db: &Connection,
+    run_id: i64,
+    file_name: &[u8],
+    old_hash: Option<&String>,
+    new_hash: Option<&String>,
+    accepted_by: &str,
+) -> Result<(), sqlite::Error> {
+    match new_hash {
+        Some(_) => {
+            let mut statement = db.prepare(format!(
+                "\
+                INSERT OR REPLACE INTO baseline \
+                    (path_id, file_entry_id, accepted_by, accepted_at) \
+                SELECT path_id, id, ?3, CURRENT_TIMESTAMP FROM file_entry \
+                    WHERE id = ({}) AND file_type != 'gone';\
+                ",
+                PATH_STATE
+            ))?;
+            statement.bind(1, run_id)?;
+            statement.bind(2, file_name)?;
+            statement.bind(3, accepted_by)?;
+            statement.next()?;
+        }
+        None => {
+            let mut statement = db.prepare(
+                "DELETE FROM baseline WHERE path_id = (SELECT id FROM path WHERE name = ?);",
+            )?;
+            statement.bind(1, file_name)?;
+            statement.next()?;
+        }
+    }
+    let mut statement = db.prepare(
+        "\
+        INSERT INTO resolution (file_name, old_hash, new_hash, run_id, accepted_by, accepted_at) \
+            VALUES (?, ?, ?, ?, ?, CURRENT_TIMESTAMP);\
+        ",
+    )?;
+    statement.bind(1, file_name)?;
+    statement.bind(2, old_hash.map(|h| h.as_str()))?;
+    statement.bind(3, new_hash.map(|h| h.as_str()))?;
+    statement.bind(4, run_id)?;
+    statement.bind(5, accepted_by)?;
+    statement.next()?;
+    Ok(())
+}
+
+// Takes a `to` digest of every file in the baseline that doesn't have one yet, in the same read
+// that checks the file against the digest it was accepted with. Only files that still match
+// get the new digest; anything else keeps its old one and is reported
+fn rehash(to: HashAlgorithm) {
+    let db = open_db_to_write("rehash", false);
+    if let Err(e) = handle_stop_signals() {
+        outln!("There was a problem setting up signal handling: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    // (file_name, file_entry_id, algorithm, digest) for every file still to do
+    let mut pending: Vec<(Vec<u8>, i64, HashAlgorithm, String)> = Vec::new();
+    let mut statement = db
+        .prepare(
+            "SELECT path.name, baseline.file_entry_id, file_entry.algorithm, \
+                file_entry.digest FROM baseline \
+                JOIN path ON path.id = baseline.path_id \
+                JOIN file_entry ON file_entry.id = baseline.file_entry_id \
+                WHERE file_type = 'file' AND file_entry.algorithm != ?1 AND NOT EXISTS ( \
+                    SELECT 1 FROM file_digest \
+                        WHERE file_entry_id = baseline.file_entry_id AND algorithm = ?1) \
+                ORDER BY path.name;",
+        )
+        .unwrap();
+    statement.bind(1, to.id()).unwrap();
+    while State::Row == statement.next().unwrap() {
+        let algorithm = statement.read::<i64>(2).unwrap();
+        let algorithm = match HashAlgorithm::from_id(algorithm) {
+            Some(algorithm) => algorithm,
+            None => {
+                outln!(
+                    "The baseline was hashed with algorithm {}, which frzr doesn't know",
+                    algorithm
+                );
+                exit(EXIT_ERROR);
+            }
+        };
+        pending.push((
+            statement.read::<Vec<u8>>(0).unwrap(),
+            statement.read::<i64>(1).unwrap(),
+            algorithm,
+            digest_to_hex(&statement.read::<Vec<u8>>(3).unwrap()),
+        ));
+    }
+    outln!(
+        "Rehashing {} baseline files to {}",
+        pending.len(),
+        to.as_str()
+    );
+
+    let limits = Limits::default();
+    let mut verified: Vec<(i64, String)> = Vec::with_capacity(WRITE_BATCH_SIZE);
+    let mut verified_count = 0;
+    let mut failed: Vec<(Vec<u8>, HashAlgorithm)> = Vec::new();
+    let mut errors: Vec<FileError> = Vec::new();
+    for (file_name, file_entry_id, algorithm, old_digest) in pending {
+        if stop_requested().is_some() {
+            break;
+        }
+        let path = PathBuf::from(OsStr::from_bytes(&file_name));
+        let hashes = RunHashes {
+            primary: algorithm,
+            extras: vec![to],
+            chunk_size: None,
+        };
+        match compute_the_hash(&path, &limits, &hashes) {
+            Ok(mut digests) if digests.file_hash == old_digest => {
+                verified.push((file_entry_id, digests.extra_digests.remove(0).1));
+            }
+            Ok(_) => failed.push((file_name, algorithm)),
+            Err(_) if stop_requested().is_some() => break,
+            Err((phase, e)) => errors.push(FileError::new(&path, phase, &e)),
+        }
+        if verified.len() < WRITE_BATCH_SIZE {
+            continue;
+        }
+        if let Err(e) = record_digests(&db, to, &verified) {
+            outln!("There was a problem recording digests: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+        verified_count += verified.len();
+        verified.clear();
+    }
+    if let Err(e) = record_digests(&db, to, &verified) {
+        outln!("There was a problem recording digests: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    verified_count += verified.len();
+    if let Some(signal) = stop_requested() {
+        eprintln!(
+            "Interrupted by {}: {} files were rehashed; `frzr rehash --to {}` will do the rest",
+            signal_name(signal),
+            verified_count,
+            to.as_str()
+        );
+        exit(128 + signal);
+    }
+    if let Err(e) = set_config(&db, HASH_ALGORITHM_KEY, to.as_str()) {
+        outln!("There was a problem saving the hash algorithm: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+
+    print_errors(&errors);
+    outln!("Verified and rehashed: {}", verified_count);
+    outln!("Failed verification: {}", failed.len());
+    for (file_name, algorithm) in &failed {
+        outln!(
+            "FAILED: {} doesn't match its {} digest in the baseline",
+            display_file_name(file_name),
+            algorithm.as_str()
+        );
+    }
+    outln!(
+        "`frzr check` now hashes with {}; files that weren't rehashed are still checked \
+         against their old digests",
+        to.as_str()
+    );
+    if !errors.is_empty() {
+        exit(EXIT_ERROR);
+    }
+    if !failed.is_empty() {
+        exit(EXIT_CHANGES);
+    }
+}
+
+// Adds (file_entry_id, digest) pairs to file_digest, all taken with algorithm
+fn record_digests(
+    db: &Connection,
+    algorithm: HashAlgorithm,
+    digests: &[(i64, String)],
+) -> Result<(), sqlite::Error> {
+    db.execute("BEGIN;")?;
+    let mut statement =
+        db.prepare("INSERT INTO file_digest (file_entry_id, algorithm, digest) VALUES (?, ?, ?);")?;
+    for (file_entry_id, digest) in digests {
+        statement.reset()?;
+        statement.bind(1, *file_entry_id)?;
+        statement.bind(2, algorithm.id())?;
+        statement.bind(3, &digest_to_bytes(digest)[..])?;
+        statement.next()?;
+    }
+    db.execute("COMMIT;")
+}
+
+// The config key for the algorithm `check` hashes with, unless given --hash
+const HASH_ALGORITHM_KEY: &str = "hash_algorithm";
+
+// The config key for how many bytes each chunk digest covers; unset to take none
+const CHUNK_SIZE_KEY: &str = "chunk_size";
+
+fn get_config(db: &Connection, key: &str) -> Result<Option<String>, sqlite::Error> {
+    let mut statement = db.prepare("SELECT value FROM config WHERE key = ?;")?;
+    statement.bind(1, key)?;
+    match statement.next()? {
+        State::Row => Ok(Some(statement.read::<String>(0)?)),
+        State::Done => Ok(None),
+    }
+}
+
+fn set_config(db: &Connection, key: &str, value: &str) -> Result<(), sqlite::Error> {
+    let mut statement = db.prepare("INSERT OR REPLACE INTO config (key, value) VALUES (?, ?);")?;
+    statement.bind(1, key)?;
+    statement.bind(2, value)?;
+    statement.next()?;
+    Ok(())
+}
+
+// Paths on the command line are relative to CWD, like the ones `check` stores
+fn path_prefixes(paths: &[String]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .map(|path| Path::new(".").join(path.trim_start_matches("./")))
+        .collect()
+}
+
+// Whether file_name is one of prefixes, or under one of them
+fn under_any(file_name: &[u8], prefixes: &[PathBuf]) -> bool {
+    let path = Path::new(OsStr::from_bytes(file_name));
+    prefixes.iter().any(|prefix| path.starts_with(prefix))
+}
+
+// Where `protect` keeps parity files, each named for its row in the parity table
+const PARITY_DIR: &str = "./.frzr/parity";
+
+fn parity_path(parity_id: i64) -> PathBuf {
+    Path::new(PARITY_DIR).join(parity_id.to_string())
+}
+
+// Writes parity for every baseline file under paths. A file is only protected if the read that
+// computes its parity also finds it still matching the baseline; parity of a damaged file
+// would only help to put the damage back
+fn protect(paths: Vec<String>, percent: u64, block_size: Option<u64>) {
+    let db = open_db_to_write("protect", false);
+    if let Err(e) = handle_stop_signals() {
+        outln!("There was a problem setting up signal handling: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    // The chunk size isn't limited the way --block-size is, since chunks are hashed one at a time
+    let block_size = match block_size {
+        Some(block_size) => block_size,
+        None => match get_config(&db, CHUNK_SIZE_KEY) {
+            Ok(chunk_size) => chunk_size
+                .and_then(|chunk_size| chunk_size.parse::<u64>().ok())
+                .map_or(parity::DEFAULT_BLOCK_SIZE, |chunk_size| {
+                    chunk_size.min(parity::MAX_BLOCK_SIZE)
+                }),
+            Err(e) => {
+                outln!("There was a problem reading the chunk size: {:?}", e);
+                exit(EXIT_ERROR);
+            }
+        },
+    };
+    if let Err(e) = fs::create_dir_all(PARITY_DIR) {
+        outln!("There was a problem creating {}: {:?}", PARITY_DIR, e);
+        exit(EXIT_ERROR);
+    }
+
+    let prefixes = path_prefixes(&paths);
+    // (file_name, file_entry_id, algorithm, digest) for every file to protect
+    let mut pending: Vec<(Vec<u8>, i64, HashAlgorithm, String)> = Vec::new();
+    let mut statement = db
+        .prepare(
+            "SELECT path.name, baseline.file_entry_id, file_entry.algorithm, \
+                file_entry.digest FROM baseline \
+                JOIN path ON path.id = baseline.path_id \
+                JOIN file_entry ON file_entry.id = baseline.file_entry_id \
+                WHERE file_type = 'file' ORDER BY path.name;",
+        )
+        .unwrap();
+    while State::Row == statement.next().unwrap() {
+        let file_name = statement.read::<Vec<u8>>(0).unwrap();
+        if !under_any(&file_name, &prefixes) {
+            continue;
+        }
+        let algorithm = statement.read::<i64>(2).unwrap();
+        let algorithm = match HashAlgorithm::from_id(algorithm) {
+            Some(algorithm) => algorithm,
+            None => {
+                outln!(
+                    "The baseline was hashed with algorithm {}, which frzr doesn't know",
+                    algorithm
+                );
+                exit(EXIT_ERROR);
+            }
+        };
+        pending.push((
+            file_name,
+            statement.read::<i64>(1).unwrap(),
+            algorithm,
+            digest_to_hex(&statement.read::<Vec<u8>>(3).unwrap()),
+        ));
+    }
+    if pending.is_empty() {
+        outln!(
+            "No baseline files under {}; run `frzr check` first",
+            paths.join(", ")
+        );
+        exit(EXIT_ERROR);
+    }
+
+    // Written here first, and only named for its row once the row is in
+    let new_parity = Path::new(PARITY_DIR).join("new");
+    let mut protected_count = 0;
+    let mut parity_bytes = 0;
+    let mut mismatched: Vec<Vec<u8>> = Vec::new();
+    let mut errors: Vec<FileError> = Vec::new();
+    for (file_name, file_entry_id, algorithm, baseline_digest) in pending {
+        if stop_requested().is_some() {
+            break;
+        }
+        let path = PathBuf::from(OsStr::from_bytes(&file_name));
+        let protected =
+            match parity::protect(&path, block_size, percent, algorithm.hasher(), &new_parity) {
+                Ok(protected) => protected,
+                Err(_) if stop_requested().is_some() => break,
+                Err(e) => {
+                    errors.push(FileError::new(&path, "read", &e));
+                    continue;
+                }
+            };
+        if protected.file_hash != baseline_digest {
+            mismatched.push(file_name);
+            continue;
+        }
+        if let Err(e) = record_parity(&db, &file_name, file_entry_id, &protected, &new_parity) {
+            outln!(
+                "There was a problem recording parity for {}: {}",
+                display_file_name(&file_name),
+                e
+            );
+            exit(EXIT_ERROR);
+        }
+        let layout = protected.layout;
+        outln!(
+            "protected: {} ({} blocks of {} bytes, {} parity blocks)",
+            display_file_name(&file_name),
+            layout.blocks(),
+            layout.block_size,
+            layout.parity_blocks()
+        );
+        protected_count += 1;
+        parity_bytes += layout.parity_blocks() * layout.block_size;
+    }
+    // Whatever was being written when we stopped, or wasn't worth keeping
+    let _ = fs::remove_file(&new_parity);
+    if let Some(signal) = stop_requested() {
+        eprintln!(
+            "Interrupted by {}: {} files were protected",
+            signal_name(signal),
+            protected_count
+        );
+        exit(128 + signal);
+    }
+
+    print_errors(&errors);
+    outln!(
+        "Protected {} files with {} bytes of parity",
+        protected_count,
+        parity_bytes
+    );
+    for file_name in &mismatched {
+        outln!(
+            "NOT PROTECTED: {} doesn't match the baseline; `frzr check` it first",
+            display_file_name(file_name)
+        );
+    }
+    if !errors.is_empty() {
+        exit(EXIT_ERROR);
+    }
+    if !mismatched.is_empty() {
+        exit(EXIT_CHANGES);
+    }
+}
+
+// Recording parity is a row in the DB and a file next to it, and either can fail
+enum RecordParityError {
+    Sqlite(sqlite::Error),
+    // Moving the parity file into place
+    Io(io::Error),
+}
+
+impl std::fmt::Display for RecordParityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RecordParityError::Sqlite(e) => write!(f, "{:?}", e),
+            RecordParityError::Io(e) => write!(f, "couldn't save the parity file: {}", e),
+        }
+    }
+}
+
+impl From<sqlite::Error> for RecordParityError {
+    fn from(e: sqlite::Error) -> RecordParityError {
+        RecordParityError::Sqlite(e)
+    }
+}
+
+// Makes new_parity the parity of file_name, in place of any it had before
+fn record_parity(
+    db: &Connection,
+    file_name: &[u8],
+    file_entry_id: i64,
+    protected: &parity::Protected,
+    new_parity: &Path,
+) -> Result<(), RecordParityError> {
+    let mut replaced = Vec::new();
+    let mut statement = db.prepare("SELECT id FROM parity WHERE file_name = ?;")?;
+    statement.bind(1, file_name)?;
+    while let State::Row = statement.next()? {
+        replaced.push(statement.read::<i64>(0)?);
+    }
+
+    db.execute("BEGIN;")?;
+    let mut statement = db.prepare(
+        "INSERT INTO parity (file_name, file_entry_id, file_size, block_size, percent, \
+            block_digests, parity_digests, created_at) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, datetime('now'));",
+    )?;
+    let layout = protected.layout;
+    statement.bind(1, file_name)?;
+    statement.bind(2, file_entry_id)?;
+    statement.bind(3, layout.file_size as i64)?;
+    statement.bind(4, layout.block_size as i64)?;
+    statement.bind(5, layout.percent as i64)?;
+    statement.bind(6, &protected.block_digests.digests[..])?;
+    statement.bind(7, &protected.parity_digests.digests[..])?;
+    statement.next()?;
+    let mut statement = db.prepare("SELECT last_insert_rowid();")?;
+    statement.next()?;
+    let parity_id = statement.read::<i64>(0)?;
+    // Only once the new row has its id, or it could be given the id of one it replaces, and
+    // lose its parity file along with that one
+    db.execute(format!(
+        "DELETE FROM parity WHERE id IN ({});",
+        replaced
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))?;
+    // Renamed before the commit, so that a row never points at a file that isn't there
+    if let Err(e) = fs::rename(new_parity, parity_path(parity_id)) {
+        db.execute("ROLLBACK;")?;
+        return Err(RecordParityError::Io(e));
+    }
+    db.execute("COMMIT;")?;
+    for id in replaced {
+        let _ = fs::remove_file(parity_path(id));
+    }
+    Ok(())
+}
+
+// One protected file, as `repair` needs it
+struct ParityRecord {
+    id: i64,
+    file_name: Vec<u8>,
+    layout: parity::Layout,
+    block_digests: Chunks,
+    parity_digests: Chunks,
+    // The file's baseline digest as it is now. None when the baseline has moved on from the
+    // entry that was protected, or the path is no longer in it, which makes the parity stale
+    baseline_digest: Option<(HashAlgorithm, String)>,
+}
+
+fn load_parity_records(db: &Connection) -> Result<Vec<ParityRecord>, sqlite::Error> {
+    let mut records = Vec::new();
+    let mut statement = db.prepare(
+        "SELECT parity.id, parity.file_name, file_size, block_size, percent, block_digests, \
+            parity_digests, file_entry.algorithm, file_entry.digest FROM parity \
+            LEFT JOIN path ON path.name = parity.file_name \
+            LEFT JOIN baseline ON baseline.path_id = path.id \
+                AND baseline.file_entry_id = parity.file_entry_id \
+            LEFT JOIN file_entry ON file_entry.id = baseline.file_entry_id \
+            ORDER BY parity.file_name;",
+    )?;
+    while let State::Row = statement.next()? {
+        let block_size = statement.read::<i64>(3)? as u64;
+        let baseline_digest = match statement.read::<Option<i64>>(7)? {
+            Some(algorithm) => Some((
+                HashAlgorithm::from_id(algorithm).unwrap_or(DEFAULT_ALGORITHM),
+                digest_to_hex(&statement.read::<Vec<u8>>(8)?),
+            )),
+            None => None,
+        };
+        records.push(ParityRecord {
+            id: statement.read::<i64>(0)?,
+            file_name: statement.read::<Vec<u8>>(1)?,
+            layout: parity::Layout {
+                file_size: statement.read::<i64>(2)? as u64,
+                block_size,
+                percent: statement.read::<i64>(4)? as u64,
+            },
+            block_digests: Chunks {
+                chunk_size: block_size,
+                digests: statement.read::<Vec<u8>>(5)?,
+            },
+            parity_digests: Chunks {
+                chunk_size: block_size,
+                digests: statement.read::<Vec<u8>>(6)?,
+            },
+            baseline_digest,
+        });
+    }
+    Ok(records)
+}
+
+// Rebuilds whatever is damaged in the protected files under paths, then reads each repaired
+// file end to end to make sure it is back to what was protected
+fn repair(paths: Vec<String>) {
+    let db = open_db_to_write("repair", false);
+    if let Err(e) = handle_stop_signals() {
+        outln!("There was a problem setting up signal handling: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    let prefixes = path_prefixes(&paths);
+    let records: Vec<ParityRecord> = match load_parity_records(&db) {
+        Ok(records) => records
+            .into_iter()
+            .filter(|record| under_any(&record.file_name, &prefixes))
+            .collect(),
+        Err(e) => {
+            outln!("There was a problem reading parity records: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    if records.is_empty() {
+        outln!(
+            "Nothing under {} is protected; `frzr protect` it first",
+            paths.join(", ")
+        );
+        exit(EXIT_ERROR);
+    }
+
+    let limits = Limits::default();
+    let mut intact_count = 0;
+    let mut repaired_count = 0;
+    let mut unrepairable: Vec<Vec<u8>> = Vec::new();
+    let mut stale: Vec<Vec<u8>> = Vec::new();
+    let mut errors: Vec<FileError> = Vec::new();
+    for record in records {
+        let path = PathBuf::from(OsStr::from_bytes(&record.file_name));
+        // Parity from before a change was accepted would undo the change, so the file isn't
+        // touched at all
+        let (algorithm, baseline_hash) = match &record.baseline_digest {
+            Some(baseline_digest) => baseline_digest,
+            None => {
+                outln!(
+                    "STALE PARITY: {}: the baseline has changed since it was protected; \
+                     `frzr protect` it again",
+                    display_file_name(&record.file_name)
+                );
+                stale.push(record.file_name);
+                continue;
+            }
+        };
+        let outcome = match parity::repair(
+            &path,
+            &record.layout,
+            &record.block_digests,
+            &record.parity_digests,
+            &parity_path(record.id),
+        ) {
+            Ok(outcome) => outcome,
+            Err(_) if stop_requested().is_some() => break,
+            Err(e) => {
+                errors.push(FileError::new(&path, "repair", &e));
+                continue;
+            }
+        };
+        let name = display_file_name(&record.file_name);
+        if !outcome.unrepairable.is_empty() {
+            outln!(
+                "COULD NOT REPAIR: {}: {} damaged blocks are in stripes with more damage than \
+                 parity; {} others were rebuilt",
+                name,
+                outcome.unrepairable.len(),
+                outcome.repaired.len()
+            );
+            unrepairable.push(record.file_name);
+            continue;
+        }
+        // Even with every block matching its digest, the whole file is what the baseline vouches
+        // for
+        let hashes = RunHashes {
+            primary: *algorithm,
+            extras: Vec::new(),
+            chunk_size: None,
+        };
+        match compute_the_hash(&path, &limits, &hashes) {
+            Ok(digests) if digests.file_hash == *baseline_hash => {}
+            Ok(_) => {
+                outln!(
+                    "COULD NOT REPAIR: {}: every block matches, but the file doesn't match its \
+                     {} digest",
+                    name,
+                    algorithm.as_str()
+                );
+                unrepairable.push(record.file_name);
+                continue;
+            }
+            Err(_) if stop_requested().is_some() => break,
+            Err((phase, e)) => {
+                errors.push(FileError::new(&path, phase, &e));
+                continue;
+            }
+        }
+        if outcome.repaired.is_empty() {
+            intact_count += 1;
+            continue;
+        }
+        let block_size = record.layout.block_size;
+        let shown: Vec<String> = outcome
+            .repaired
+            .iter()
+            .take(MAX_RANGES_SHOWN)
+            .map(|index| {
+                let end = ((index + 1) * block_size).min(record.layout.file_size);
+                format!("{}-{}", index * block_size, end - 1)
+            })
+            .collect();
+        outln!(
+            "repaired: {}: {} of {} blocks of {} bytes: {}{}",
+            name,
+            outcome.repaired.len(),
+            record.layout.blocks(),
+            block_size,
+            shown.join(", "),
+            if outcome.repaired.len() > MAX_RANGES_SHOWN {
+                format!(", and {} more", outcome.repaired.len() - MAX_RANGES_SHOWN)
+            } else {
+                String::new()
+            }
+        );
+        repaired_count += 1;
+    }
+    if let Some(signal) = stop_requested() {
+        eprintln!(
+            "Interrupted by {}: {} files were repaired",
+            signal_name(signal),
+            repaired_count
+        );
+        exit(128 + signal);
+    }
+
+    print_errors(&errors);
+    outln!("Intact:   {}", intact_count);
+    outln!("Repaired: {}", repaired_count);
+    outln!("Could not repair: {}", unrepairable.len());
+    outln!("Stale parity: {}", stale.len());
+    if repaired_count > 0 {
+        outln!("Repaired files match the baseline again; the next `frzr check` will agree");
+    }
+    if !errors.is_empty() {
+        exit(EXIT_ERROR);
+    }
+    if !unrepairable.is_empty() || !stale.is_empty() {
+        exit(EXIT_CHANGES);
+    }
+}
+
+fn status() {
+    let db = open_db_to_read("status");
+    // A check that is running right now has the lock, and says how far it has got
+    let running = match lock::holder() {
+        Ok(holder) => holder.filter(|holder| holder.verb == "check" && holder.is_alive()),
+        Err(e) => {
+            outln!("There was a problem reading the lock file: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    if let Some(holder) = &running {
+        let progress = match Progress::read() {
+            Ok(progress) => progress.filter(|progress| progress.pid == holder.pid),
+            Err(e) => {
+                outln!("There was a problem reading the progress file: {:?}", e);
+                exit(EXIT_ERROR);
+            }
+        };
+        match progress {
+            Some(progress) => print_progress(holder, &progress),
+            // It hasn't got far enough to say, or has hashed everything and is wrapping up
+            None => outln!("Running: {}", holder),
+        }
+    }
+    if let Err(e) = print_run_summaries(&db, running.is_some()) {
+        outln!("There was a problem reading the runs: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    exit(EXIT_CLEAN);
+}
+
+fn print_progress(holder: &lock::Holder, progress: &Progress) {
+    outln!("Run {} is running: {}", progress.run_id, holder);
+    let percent = |done: u64, expected: u64| done * 100 / expected.max(1);
+    match progress.files_expected {
+        // The total is only a guess, and this run can find more than the last one did
+        Some(expected) if expected >= progress.files_done => outln!(
+            "Files:     {} of about {} ({}%)",
+            progress.files_done,
+            expected,
+            percent(progress.files_done, expected)
+        ),
+        _ => outln!("Files:     {}", progress.files_done),
+    }
+    match progress.bytes_expected {
+        Some(expected) if expected >= progress.bytes_done => outln!(
+            "Bytes:     {} of about {} ({}%)",
+            format_mib(progress.bytes_done),
+            format_mib(expected),
+            percent(progress.bytes_done, expected)
+        ),
+        _ => outln!("Bytes:     {}", format_mib(progress.bytes_done)),
+    }
+    let elapsed = progress.updated.saturating_sub(progress.started).max(1);
+    outln!(
+        "Read:      {} at {}/s, {} files/s",
+        format_mib(progress.bytes_hashed),
+        format_mib(progress.bytes_hashed / elapsed),
+        (progress.files_done - progress.files_before) / elapsed
+    );
+    outln!("Errors:    {}", progress.errors);
+    match progress.eta() {
+        Some(eta) => outln!("Remaining: about {}", format_seconds(eta)),
+        None => outln!("Remaining: unknown"),
+    }
+}
+
+// The latest run if it didn't finish and isn't still running, then the last one that finished
+fn print_run_summaries(db: &Connection, check_running: bool) -> Result<(), sqlite::Error> {
+    let mut statement = db.prepare(
+        "SELECT id, status, abort_reason, \
+            (SELECT COUNT(*) FROM path WHERE last_seen_run = run.id) \
+            FROM run ORDER BY id DESC LIMIT 1;",
+    )?;
+    if let State::Row = statement.next()? {
+        let run_id = statement.read::<i64>(0)?;
+        let status = statement.read::<String>(1)?;
+        if status != "finished" && !check_running {
+            let reason = match statement.read::<Option<String>>(2)? {
+                Some(abort_reason) => abort_reason,
+                None => "it was killed".to_string(),
+            };
+            outln!(
+                "Run {} didn't finish ({}) after recording {} files; `frzr check --resume` \
+                 will finish it",
+                run_id,
+                reason,
+                statement.read::<i64>(3)?
+            );
+        }
+    }
+    let mut statement = db.prepare(
+        "SELECT id, start_time, end_time, mode, hash_algorithm FROM run \
+            WHERE status = 'finished' ORDER BY id DESC LIMIT 1;",
+    )?;
+    if let State::Done = statement.next()? {
+        outln!("No finished runs in the DB yet; run `frzr check` first");
+        return Ok(());
+    }
+    let run_id = statement.read::<i64>(0)?;
+    let (files, _) = count_entries(db, run_id, false)?;
+    let errors = load_run_errors(db, run_id)?;
+    outln!(
+        "Last finished run: {} ({}, {})",
+        run_id,
+        statement.read::<String>(3)?,
+        statement.read::<String>(4)?
+    );
+    outln!("Started:   {} UTC", statement.read::<String>(1)?);
+    outln!("Finished:  {} UTC", statement.read::<String>(2)?);
+    outln!("Files:     {}", files);
+    outln!("Errors:    {}", errors.len());
+    print_errors(&errors);
+    if !baseline_established(db)? {
+        outln!("Changes:   none; there is no baseline yet");
+        return Ok(());
+    }
+    // Against the baseline as it is now, so whatever was resolved since doesn't count
+    let mut diff = diff_entries(
+        EntryCursor::baseline(db)?,
+        EntryCursor::run(db, run_id, false)?,
+    )?;
+    diff.set_aside_unreadable(&errors);
+    let counts: Vec<String> = [
+        ("corrupted", diff.corrupted.len()),
+        ("edited", diff.edited.len()),
+        ("modified", diff.modified.len()),
+        ("retargeted", diff.retargeted.len()),
+        ("type changed", diff.type_changed.len()),
+        ("added", diff.added.len()),
+        ("missing", diff.missing.len()),
+    ]
+    .iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(kind, count)| format!("{} {}", count, kind))
+    .collect();
+    if counts.is_empty() {
+        outln!("Changes:   none");
+    } else {
+        outln!(
+            "Changes:   {} against the baseline; `frzr report` has the details",
+            counts.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn format_mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn format_seconds(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds / 60 % 60),
+    }
+}
+
+// Opens the DB for a verb that writes to it, once it has the lock, so that nothing else writes
+// while it is upgraded, if it needs to be, or afterwards
+fn open_db_to_write(verb: &str, wait: bool) -> Connection {
+    // Without a DB there is no .frzr to put the lock in, either
+    if !schema::initialized() {
+        outln!(
+            "There was a problem opening the DB: {}",
+            schema::DbError::NotInitialized
+        );
+        exit(EXIT_ERROR);
+    }
+    // Whoever has the lock on a DB that still needs upgrading is upgrading it, which doesn't take
+    // long, and a reader that had to lets go of it again right after
+    let upgrading = matches!(
+        schema::open_db(schema::Access::Read),
+        Err(schema::DbError::Outdated)
+    );
+    lock_db(verb, wait || upgrading);
+    match schema::open_db(schema::Access::Write) {
+        Ok(db) => db,
+        Err(e) => {
+            outln!("There was a problem opening the DB: {}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+}
+
+// Opens the DB for a verb that only reads it, which doesn't need the lock. Upgrading a DB that
+// an older frzr wrote is writing, though, so that takes the lock like any other write, just for
+// as long as the upgrade takes. If a writer has it, that writer is upgrading the DB itself, so
+// this waits for it rather than give up
+fn open_db_to_read(verb: &str) -> Connection {
+    match schema::open_db(schema::Access::Read) {
+        Ok(db) => db,
+        Err(schema::DbError::Outdated) => {
+            let db = open_db_to_write(verb, true);
+            lock::release();
+            db
+        }
+        Err(e) => {
+            outln!("There was a problem opening the DB: {}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+}
+
+// Takes the DB's write lock for the rest of the process, or exits saying who has it
+fn lock_db(verb: &str, wait: bool) {
+    let stale = match lock::acquire(verb, false) {
+        Ok(stale) => stale,
+        Err(LockError::Held(holder)) if wait => {
+            match holder {
+                Some(holder) => eprintln!("Waiting for {} to finish", holder),
+                None => eprintln!("Waiting for another frzr to finish"),
+            }
+            match lock::acquire(verb, true) {
+                Ok(stale) => stale,
+                Err(e) => {
+                    outln!("There was a problem locking the DB: {}", e);
+                    exit(EXIT_ERROR);
+                }
+            }
+        }
+        Err(e @ LockError::Held(_)) => {
+            outln!("Not going ahead: {}", e);
+            if verb == "check" {
+                outln!("`frzr check --wait` waits for it to finish");
+            }
+            exit(EXIT_ERROR);
+        }
+        Err(e) => {
+            outln!("There was a problem locking the DB: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    if let Some(holder) = stale {
+        eprintln!(
+            "Found a stale lock: {} was killed before it finished",
+            holder
+        );
+    }
+}
+
+fn fsck() {
+    let db = open_db_to_read("fsck");
+    let problems = match fsck::check_consistency(&db) {
+        Ok(problems) => problems,
+        Err(e) => {
+            outln!("There was a problem checking the DB: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    for problem in &problems {
+        outln!("PROBLEM: {}", problem);
+    }
+    if !problems.is_empty() {
+        outln!("{} problems with {}", problems.len(), schema::DB_PATH);
+        exit(EXIT_CHANGES);
+    }
+    let unfinished = match find_unfinished_run(&db) {
+        Ok(unfinished) => unfinished,
+        Err(e) => {
+            outln!("There was a problem looking for an unfinished run: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    outln!("{} is consistent", schema::DB_PATH);
+    // Not a problem: everything it did get to is there, and the rest is still to do
+    if let Some((run_id, _, _)) = unfinished {
+        outln!(
+            "Run {} didn't finish; `frzr check --resume` will finish it",
+            run_id
+        );
+    }
+    exit(EXIT_CLEAN);
+}
+
+// Prints a question and reads one trimmed line of an answer; None if stdin is closed
+fn prompt(question: &str) -> Option<String> {
+    print!("{}", question);
+    io::stdout().flush().ok()?;
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
+fn display_file_name(file_name: &[u8]) -> String {
+    // TODO: print nasty filenames better
+    Path::new(OsStr::from_bytes(file_name))
+        .display()
+        .to_string()
+}
+
+fn init(hash_algorithm: HashAlgorithm, chunk_size: Option<u64>) {
+    // TODO: This is how I expect init to work:
+    //       1. Check if .frzr directory exists. If it does, bail with message
+    //       2. Create .frzr directory in the current directory
+    //       3. Initialize the DB there
+    let db_path = Path::new("./.frzr/");
+    let path_exists = match db_path.try_exists() {
+        Ok(exists) => exists,
+        Err(e) => {
+            // TODO Maybe print to stderr here:
+            outln!("Error checking for existing .frzr directory; not taking any action!");
+            outln!("Error was: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    if path_exists {
+        // TODO Maybe print to stderr here:
+        outln!(".frzr directory already exists; not taking any action!");
+        exit(EXIT_ERROR);
+    };
+    match std::fs::create_dir(db_path) {
+        Ok(ok_val) => ok_val,
+        Err(e) => {
+            // TODO Maybe print to stderr here:
+            outln!("Error creating .frzr directory! Error was: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    // FUTURE: Maybe return the schema version as well as the connection?
+    let db = match schema::create_db() {
+        Ok(db) => db,
+        Err(e) => {
+            outln!("There was a problem initializing the DB: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    if let Err(e) = set_config(&db, HASH_ALGORITHM_KEY, hash_algorithm.as_str()) {
+        outln!("There was a problem saving the hash algorithm: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    if let Some(chunk_size) = chunk_size {
+        if let Err(e) = set_config(&db, CHUNK_SIZE_KEY, &chunk_size.to_string()) {
+            outln!("There was a problem saving the chunk size: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+    // If we get here, then the db is open and ready for business
+}
+
+// How much `check` trusts the previous run
+#[derive(Clone, Copy, PartialEq)]
+enum CheckMode {
+    // Every byte of every file is read again. This is the only mode that can spot bitrot, since
+    // corruption doesn't touch a file's stat
+    Full,
+    // A file whose vitals match the previous run keeps that run's hash without being read, so
+    // only new and changed files are hashed
+    Quick,
+}
+
+impl CheckMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CheckMode::Full => "full",
+            CheckMode::Quick => "quick",
+        }
+    }
+
+    fn from_str(mode: &str) -> Option<CheckMode> {
+        match mode {
+            "full" => Some(CheckMode::Full),
+            "quick" => Some(CheckMode::Quick),
+            _ => None,
+        }
+    }
+}
+
+// The id and mode of the latest run, if it never got to the end. An unfinished run that has
+// been followed by a finished one is stale and isn't worth resuming
+fn find_unfinished_run(
+    db: &Connection,
+) -> Result<Option<(i64, CheckMode, HashAlgorithm)>, sqlite::Error> {
+    let mut statement =
+        db.prepare("SELECT id, end_time, mode, hash_algorithm FROM run ORDER BY id DESC LIMIT 1;")?;
+    if let State::Done = statement.next()? {
+        return Ok(None);
+    }
+    if statement.read::<Option<String>>(1)?.is_some() {
+        return Ok(None);
+    }
+    let run_id = statement.read::<i64>(0)?;
+    let mode = CheckMode::from_str(&statement.read::<String>(2)?).unwrap_or(CheckMode::Full);
+    let hash_algorithm =
+        HashAlgorithm::from_str(&statement.read::<String>(3)?).unwrap_or(DEFAULT_ALGORITHM);
+    Ok(Some((run_id, mode, hash_algorithm)))
+}
+
+// What the walk does when it comes across a symlink
+#[derive(Clone, Copy, PartialEq)]
+enum SymlinkPolicy {
+    // Record the link itself, with where it points, so that a link being retargeted shows up
+    Record,
+    // Check whatever the link points to as if it were there instead, going into directories.
+    // A link back into a directory we are already inside is reported as a loop
+    Follow,
+    // Act as if symlinks don't exist
+    Skip,
+}
+
+impl SymlinkPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SymlinkPolicy::Record => "record",
+            SymlinkPolicy::Follow => "follow",
+            SymlinkPolicy::Skip => "skip",
+        }
+    }
+
+    fn from_str(symlinks: &str) -> Option<SymlinkPolicy> {
+        match symlinks {
+            "record" => Some(SymlinkPolicy::Record),
+            "follow" => Some(SymlinkPolicy::Follow),
+            "skip" => Some(SymlinkPolicy::Skip),
+            _ => None,
+        }
+    }
+}
+
+// The digests a run takes of every file. file_hash is taken with primary, and the rest go in
+// file_digest
+struct RunHashes {
+    primary: HashAlgorithm,
+    extras: Vec<HashAlgorithm>,
+    // Chunk digests go in file_chunks
+    chunk_size: Option<u64>,
+}
+
+// Everything compute_the_hash takes from one read of a file
+struct Digests {
+    file_hash: String,
+    extra_digests: Vec<(HashAlgorithm, String)>,
+    chunks: Option<Chunks>,
+}
+
+// Everything `check` can be told on the command line
+struct CheckOptions {
+    mode: CheckMode,
+    // Carry on with the latest run if it was interrupted, instead of starting a new one
+    resume: bool,
+    symlinks: SymlinkPolicy,
+    // Patterns from --exclude and --include, applied on top of any .frzrignore
+    excludes: Vec<String>,
+    includes: Vec<String>,
+    // Mount points under the root are recorded but not gone into
+    one_file_system: bool,
+    // None to use the default from `frzr init`
+    hash: Option<HashAlgorithm>,
+    also_hash: Vec<HashAlgorithm>,
+    // None to use the default from `frzr init`, if it set one
+    chunk_size: Option<u64>,
+    jobs: usize,
+    limits: Limits,
+    nice: bool,
+    // How many files go into each transaction
+    batch_size: usize,
+    // Print throughput at the end
+    benchmark: bool,
+    // Wait for the DB's lock instead of giving up when another frzr has it
+    wait: bool,
+}
+
+fn check(options: CheckOptions) {
+    let started = Instant::now();
+    let db = open_db_to_write("check", options.wait);
+    if options.nice {
+        // Before any other thread exists, the signal handler's included, so that they inherit it
+        if let Err(e) = throttle::lower_priority() {
+            outln!(
+                "There was a problem lowering the scheduling priority: {:?}",
+                e
+            );
+            exit(EXIT_ERROR);
+        }
+    }
+    if let Err(e) = handle_stop_signals() {
+        outln!("There was a problem setting up signal handling: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    let ignores = match IgnoreStack::new(&options.excludes, &options.includes) {
+        Ok(ignores) => ignores,
+        Err(e) => {
+            outln!("There was a problem with --exclude or --include: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    let unfinished_run = if options.resume {
+        match find_unfinished_run(&db) {
+            Ok(unfinished_run) => unfinished_run,
+            Err(e) => {
+                outln!("There was a problem looking for an unfinished run: {:?}", e);
+                exit(EXIT_ERROR);
+            }
+        }
+    } else {
+        None
+    };
+    // A resumed run carries on in the mode it was started in
+    let mode = match &unfinished_run {
+        Some((_, mode, _)) => *mode,
+        None => options.mode,
+    };
+    let primary = match &unfinished_run {
+        Some((_, _, hash_algorithm)) => *hash_algorithm,
+        None => match options.hash {
+            Some(hash_algorithm) => hash_algorithm,
+            None => match get_config(&db, HASH_ALGORITHM_KEY) {
+                Ok(configured) => configured
+                    .and_then(|algorithm| HashAlgorithm::from_str(&algorithm))
+                    .unwrap_or(DEFAULT_ALGORITHM),
+                Err(e) => {
+                    outln!("There was a problem reading the config: {:?}", e);
+                    exit(EXIT_ERROR);
+                }
+            },
+        },
+    };
+    // Whatever the baseline was hashed with gets taken too, or there would be nothing to
+    // compare against
+    let mut extras = options.also_hash.clone();
+    match baseline_hash_algorithms(&db, primary) {
+        Ok(algorithms) => extras.extend(algorithms),
+        Err(e) => {
+            outln!("There was a problem reading the baseline: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+    extras.sort();
+    extras.dedup();
+    extras.retain(|algorithm| *algorithm != primary);
+    let chunk_size = match options.chunk_size {
+        Some(chunk_size) => Some(chunk_size),
+        None => match get_config(&db, CHUNK_SIZE_KEY) {
+            Ok(configured) => configured.and_then(|chunk_size| chunk_size.parse().ok()),
+            Err(e) => {
+                outln!("There was a problem reading the config: {:?}", e);
+                exit(EXIT_ERROR);
+            }
+        },
+    };
+    let hashes = RunHashes {
+        primary,
+        extras,
+        chunk_size,
+    };
+    // Only what changed since the latest finished run gets written, and quick mode reuses its
+    // hashes. Its entries are looked up one at a time as the walk turns each path up
+    let previous_run_id = match latest_finished_run(&db) {
+        Ok(previous_run_id) => previous_run_id,
+        Err(e) => {
+            outln!("There was a problem reading the previous run: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    // The files, and bytes, the run had already got through if it is being resumed
+    let mut done_before = (0, 0);
+    let mut current_run_id = 0;
+    if let Some((unfinished_run_id, _, _)) = unfinished_run {
+        current_run_id = unfinished_run_id;
+        if let Err(e) = restart_run(&db, current_run_id) {
+            outln!(
+                "There was a problem restarting run {}: {:?}",
+                current_run_id,
+                e
+            );
+            exit(EXIT_ERROR);
+        }
+        // Whatever the run already recorded is kept as-is, and only the rest gets hashed
+        done_before = match count_entries(&db, current_run_id, true) {
+            Ok(counts) => counts,
+            Err(e) => {
+                outln!(
+                    "There was a problem reading run {}: {:?}",
+                    current_run_id,
+                    e
+                );
+                exit(EXIT_ERROR);
+            }
+        };
+        outln!(
+            "Resuming {} run {}: {} files already done",
+            mode.as_str(),
+            current_run_id,
+            done_before.0
+        );
+    } else {
+        if options.resume {
+            outln!("There is no unfinished run to resume; starting a new one");
+        }
+        let mut statement = db
+            .prepare(
+                "INSERT INTO run (start_time, mode, status, symlinks, hash_algorithm) \
+                    VALUES (CURRENT_TIMESTAMP, ?, 'running', ?, ?);",
+            )
+            .unwrap();
+        statement.bind(1, mode.as_str()).unwrap();
+        statement.bind(2, options.symlinks.as_str()).unwrap();
+        statement.bind(3, primary.as_str()).unwrap();
+        match statement.next() {
+            Ok(_) => (), // TODO use the function/map that does this prettier
+            Err(e) => {
+                outln!("There was a problem starting a run: {:?}", e);
+                exit(EXIT_ERROR);
+            }
+        };
+        let mut statement = db
+            .prepare("SELECT id FROM run ORDER BY id DESC LIMIT 1;")
+            .unwrap();
+        while State::Row == statement.next().unwrap() {
+            current_run_id = statement.read::<i64>(0).unwrap();
+        }
+    }
+
+    // The walk and the workers hash as they go; this thread is the only one that talks to the
+    // DB, writing their results out a batch (and a transaction) at a time
+    let walker = Walker::new(
+        PathBuf::from("."),
+        ignores,
+        options.symlinks,
+        options.one_file_system,
+    );
+    // Until the walk is over, the previous finished run stands in for what this one will come to
+    let expected = match previous_run_id.map(|run_id| count_entries(&db, run_id, false)) {
+        Some(Ok(counts)) => Some(counts).filter(|(files, _)| *files > 0),
+        Some(Err(e)) => {
+            outln!("There was a problem reading the previous run: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+        None => None,
+    };
+    // The walk looks things up as it goes, on a connection of its own
+    let walk_db = match schema::open_db(schema::Access::Read) {
+        Ok(walk_db) => walk_db,
+        Err(e) => {
+            outln!("There was a problem opening the DB: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    let lookup = WalkLookup {
+        db: walk_db,
+        run_id: current_run_id,
+        resumed: unfinished_run.is_some(),
+        previous_run_id,
+    };
+    let (results, walk) = hash_files(
+        walker,
+        lookup,
+        mode == CheckMode::Quick,
+        options.jobs,
+        Arc::new(options.limits),
+        options.symlinks,
+        Arc::new(hashes),
+    );
+    let mut writer = match EntryWriter::new(&db, current_run_id) {
+        Ok(writer) => writer,
+        Err(e) => {
+            outln!(
+                "There was a problem preparing to record file entries: {:?}",
+                e
+            );
+            exit(EXIT_ERROR);
+        }
+    };
+    let size_of = |entry: &FileEntry| entry.vitals.as_ref().map_or(0, |v| v.size as u64);
+    let mut progress = Progress {
+        run_id: current_run_id,
+        pid: std::process::id(),
+        started: progress::now(),
+        updated: 0,
+        files_done: done_before.0,
+        bytes_done: done_before.1,
+        files_before: done_before.0,
+        bytes_before: done_before.1,
+        bytes_hashed: 0,
+        errors: 0,
+        files_expected: expected.map(|(files, _)| files),
+        bytes_expected: expected.map(|(_, bytes)| bytes),
+    };
+    let mut reused_count = 0;
+    let mut errors: Vec<FileError> = Vec::new();
+    let mut batch: Vec<HashedFile> = Vec::with_capacity(options.batch_size);
+    let mut flush = |batch: &mut Vec<HashedFile>| {
+        if let Err(e) = writer.write(batch) {
+            outln!("There was a problem recording file entries: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+        for hashed_file in batch.drain(..) {
+            reused_count += hashed_file.reused as usize;
+        }
+    };
+    let mut last_commit = Instant::now();
+    let mut last_published = Instant::now();
+    for result in results {
+        match result {
+            Ok(hashed_file) => {
+                progress.files_done += 1;
+                progress.bytes_done += size_of(&hashed_file.entry);
+                if !hashed_file.reused {
+                    progress.bytes_hashed += size_of(&hashed_file.entry);
+                }
+                batch.push(hashed_file);
+            }
+            // A file whose hashing was cut short by a signal is left for --resume
+            Err(error) if error.interrupted && stop_requested().is_some() => continue,
+            Err(error) => {
+                // An unreadable file is exactly what we are here to find out about, so it gets
+                // recorded and the run carries on
+                if let Err(e) = record_error(&db, current_run_id, &error) {
+                    outln!("There was a problem recording an error: {:?}", e);
+                    exit(EXIT_ERROR);
+                }
+                progress.errors += 1;
+                errors.push(error);
+            }
+        }
+        // Only for `frzr status` to look at, so a failure to write it isn't worth stopping for
+        if last_published.elapsed() >= PROGRESS_INTERVAL {
+            let _ = progress.publish();
+            last_published = Instant::now();
+        }
+        // A slow walk still commits every so often, so that an interrupted run loses little
+        if batch.len() < options.batch_size && last_commit.elapsed() < COMMIT_INTERVAL {
+            continue;
+        }
+        flush(&mut batch);
+        last_commit = Instant::now();
+    }
+    flush(&mut batch);
+    let _ = Progress::clear();
+    // Every result is in, so the walk is over (or was cut short by a signal)
+    let walker = match walk.join().unwrap() {
+        Ok(walker) => walker,
+        Err(e) => {
+            outln!(
+                "There was a problem reading the DB during the walk: {:?}",
+                e
+            );
+            exit(EXIT_ERROR);
+        }
+    };
+    let ignores = walker.ignores;
+    if let Err(e) = record_ignore_rules(&db, current_run_id, &ignores.rules) {
+        outln!("There was a problem recording the ignore rules: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    if let Err(e) = record_mounts(&db, current_run_id, &walker.mounts) {
+        outln!("There was a problem recording the mount points: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    if let Some(signal) = stop_requested() {
+        // Everything that was hashed has been written out above, so the run can be picked up
+        // again where it stopped
+        let mut statement = db
+            .prepare("UPDATE run SET status = 'aborted', abort_reason = ? WHERE id = ?;")
+            .unwrap();
+        statement
+            .bind(
+                1,
+                format!("interrupted by {}", signal_name(signal)).as_str(),
+            )
+            .unwrap();
+        statement.bind(2, current_run_id).unwrap();
+        statement.next().unwrap();
+        eprintln!(
+            "Interrupted by {}: run {} was aborted after recording {} files; \
+             `frzr check --resume` will finish it",
+            signal_name(signal),
+            current_run_id,
+            progress.files_done
+        );
+        exit(128 + signal);
+    }
+    // If we haven't crashed yet, then the run exists in the DB, the file_entry rows exist in
+    // the db, and the run can be marked finished
+    if let Err(e) = finish_run(&db, current_run_id) {
+        outln!(
+            "There was a problem finishing run {}: {:?}",
+            current_run_id,
+            e
+        );
+        exit(EXIT_ERROR);
+    }
+    if options.benchmark {
+        print_benchmark(started.elapsed(), &progress, &writer, options.batch_size);
+    }
+
+    let established = match baseline_established(&db) {
+        Ok(established) => established,
+        Err(e) => {
+            outln!("There was a problem reading the baseline: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    if !established {
+        // Nothing has ever been accepted, so there is nothing to compare against. The first
+        // run is taken as-is; from here on, changes need to go through `frzr resolve`
+        if let Err(e) = seed_baseline(&db, current_run_id) {
+            outln!("There was a problem establishing the baseline: {:?}", e);
+            exit(EXIT_ERROR);
+        }
+        outln!(
+            "Run {}: established the baseline with {} files",
+            current_run_id,
+            progress.files_done
+        );
+        print_errors(&errors);
+        if !errors.is_empty() {
+            exit(EXIT_ERROR);
+        }
+        exit(EXIT_CLEAN);
+    }
+
+    // Both sides come straight from the DB in path order, rather than every file being held
+    let diff = EntryCursor::baseline(&db)
+        .and_then(|baseline| diff_entries(baseline, EntryCursor::run(&db, current_run_id, true)?));
+    let mut diff = match diff {
+        Ok(diff) => diff,
+        Err(e) => {
+            outln!(
+                "There was a problem comparing against the baseline: {:?}",
+                e
+            );
+            exit(EXIT_ERROR);
+        }
+    };
+    diff.set_aside_unreadable(&errors);
+    outln!(
+        "Run {}: checked {} files against the baseline",
+        current_run_id,
+        progress.files_done
+    );
+    print_errors(&errors);
+    let explained = IgnoreStack::from_rules(&ignores.rules);
+    if mode == CheckMode::Quick {
+        outln!(
+            "Quick mode: {} files were unchanged on disk and not reread",
+            reused_count
+        );
+    }
+    outln!("Unchanged: {}", diff.unchanged);
+    print_changes(&diff, &explained);
+    if let Err(e) = print_changed_ranges(
+        &db,
+        &ChunkSource::Baseline,
+        &ChunkSource::Run(current_run_id),
+        &diff,
+    ) {
+        outln!("There was a problem reading chunk digests: {:?}", e);
+        exit(EXIT_ERROR);
+    }
+    if !errors.is_empty() {
+        exit(EXIT_ERROR);
+    }
+    if !diff.has_changes() {
+        exit(EXIT_CLEAN);
+    }
+    exit(EXIT_CHANGES);
+}
+
+// Sets an interrupted run going again. Files that failed are tried again, and the walk is about
+// to be redone, so the errors, ignore rules and mounts from last time are stale
+fn restart_run(db: &Connection, run_id: i64) -> Result<(), sqlite::Error> {
+    db.execute("BEGIN;")?;
+    for query in [
+        "UPDATE run SET status = 'running', abort_reason = NULL WHERE id = ?;",
+        "DELETE FROM file_error WHERE run_id = ?;",
+        "DELETE FROM run_ignore_rule WHERE run_id = ?;",
+        "DELETE FROM run_mount WHERE run_id = ?;",
+    ] {
+        let mut statement = db.prepare(query)?;
+        statement.bind(1, run_id)?;
+        statement.next()?;
+    }
+    db.execute("COMMIT;")
+}
+
+// Marks every path the run didn't find as gone, along with the run as finished. Until then,
+// the paths it hasn't got to yet still look the way they did. A path the run couldn't read, or
+// that is under a directory it couldn't read, isn't gone; it keeps looking the way it did
+fn finish_run(db: &Connection, run_id: i64) -> Result<(), sqlite::Error> {
+    db.execute("BEGIN;")?;
+    let mut statement = db.prepare(format!(
+        "INSERT INTO file_entry (path_id, run_id, file_type) \
+            SELECT path_id, ?1, 'gone' FROM file_entry \
+            JOIN path ON path.id = file_entry.path_id \
+            WHERE file_entry.id IN (SELECT id FROM ({})) AND file_type != 'gone' \
+                AND last_seen_run IS NOT ?1 \
+                AND NOT EXISTS (SELECT 1 FROM file_error WHERE file_error.run_id = ?1 \
+                    AND substr(path.name, 1, length(file_error.file_name)) \
+                        = file_error.file_name \
+                    AND substr(path.name, length(file_error.file_name) + 1, 1) IN (X'', X'2F'));",
+        RUN_STATE
+    ))?;
+    statement.bind(1, run_id)?;
+    statement.next()?;
+    let mut statement = db.prepare(
+        "UPDATE run set end_time = CURRENT_TIMESTAMP, status = 'finished' WHERE id = ?;",
+    )?;
+    statement.bind(1, run_id)?;
+    statement.next()?;
+    db.execute("COMMIT;")
+}
+
+// A file or directory that couldn't be checked
+struct FileError {
+    file_name: Vec<u8>,
+    // Which step failed: "stat", "open" or "read"
+    phase: String,
+    errno: Option<i64>,
+    message: String,
+    // Whether this is just us giving up on the file because a signal asked us to stop
+    interrupted: bool,
+}
+
+impl FileError {
+    fn new(path: &Path, phase: &str, error: &io::Error) -> FileError {
+        FileError {
+            file_name: path.as_os_str().as_bytes().to_vec(),
+            phase: phase.to_string(),
+            errno: error.raw_os_error().map(|errno| errno as i64),
+            message: error.to_string(),
+            interrupted: error.kind() == io::ErrorKind::Interrupted,
+        }
+    }
+}
+
+fn record_error(db: &Connection, run_id: i64, error: &FileError) -> Result<(), sqlite::Error> {
+    let mut statement = db.prepare(
+        "INSERT INTO file_error (run_id, file_name, phase, errno, message) VALUES (?, ?, ?, ?, ?);",
+    )?;
+    statement.bind(1, run_id)?;
+    statement.bind(2, &error.file_name[..])?;
+    statement.bind(3, error.phase.as_str())?;
+    statement.bind(4, error.errno)?;
+    statement.bind(5, error.message.as_str())?;
+    statement.next()?;
+    Ok(())
+}
+
+fn record_ignore_rules(
+    db: &Connection,
+    run_id: i64,
+    rules: &[IgnoreRule],
+) -> Result<(), sqlite::Error> {
+    db.execute("BEGIN;")?;
+    let mut statement =
+        db.prepare("INSERT INTO run_ignore_rule (run_id, source, pattern) VALUES (?, ?, ?);")?;
+    for rule in rules {
+        statement.reset()?;
+        statement.bind(1, run_id)?;
+        statement.bind(2, rule.source.as_str())?;
+        statement.bind(3, rule.pattern.as_str())?;
+        statement.next()?;
+    }
+    db.execute("COMMIT;")
+}
+
+fn record_mounts(db: &Connection, run_id: i64, mounts: &[Mount]) -> Result<(), sqlite::Error> {
+    db.execute("BEGIN;")?;
+    let mut statement = db.prepare(
+        "INSERT INTO run_mount (run_id, path, device, fs_type, source) VALUES (?, ?, ?, ?, ?);",
+    )?;
+    for mount in mounts {
+        statement.reset()?;
+        statement.bind(1, run_id)?;
+        statement.bind(2, &mount.path[..])?;
+        statement.bind(3, mount.device as i64)?;
+        statement.bind(4, mount.fs_type.as_str())?;
+        statement.bind(5, mount.source.as_str())?;
+        statement.next()?;
+    }
+    db.execute("COMMIT;")
+}
+
+// Map of path -> mount for every mount point a run came across, its root included
+fn load_mounts(db: &Connection, run_id: i64) -> Result<BTreeMap<Vec<u8>, Mount>, sqlite::Error> {
+    let mut mounts = BTreeMap::new();
+    let mut statement =
+        db.prepare("SELECT path, device, fs_type, source FROM run_mount WHERE run_id = ?;")?;
+    statement.bind(1, run_id)?;
+    while let State::Row = statement.next()? {
+        let mount = Mount {
+            path: statement.read::<Vec<u8>>(0)?,
+            device: statement.read::<i64>(1)? as u64,
+            fs_type: statement.read::<String>(2)?,
+            source: statement.read::<String>(3)?,
+        };
+        mounts.insert(mount.path.clone(), mount);
+    }
+    Ok(mounts)
+}
+
+// Warns about every mount point that is on a different device than in the previous run, or
+// that only one of the runs saw. Files that went missing under one are counted, since the
+// likeliest explanation for them is the mount rather than the files
+fn print_mount_changes(
+    previous: &BTreeMap<Vec<u8>, Mount>,
+    latest: &BTreeMap<Vec<u8>, Mount>,
+    missing: &[Vec<u8>],
+) {
+    let paths: BTreeSet<&Vec<u8>> = previous.keys().chain(latest.keys()).collect();
+    for path in paths {
+        let missing_under = missing
+            .iter()
+            .filter(|file_name| {
+                Path::new(OsStr::from_bytes(file_name)).starts_with(OsStr::from_bytes(path))
+            })
+            .count();
+        let change = match (previous.get(path), latest.get(path)) {
+            (Some(before), Some(now)) if before.device != now.device => {
+                format!("was on {}, now on {}", before.describe(), now.describe())
+            }
+            (Some(before), None) => format!(
+                "was a mount point of {}, and isn't any more",
+                before.describe()
+            ),
+            (None, Some(now)) => format!("is newly a mount point of {}", now.describe()),
+            _ => continue,
+        };
+        outln!(
+            "MOUNT CHANGED: {} {}; {} missing files are under it",
+            display_file_name(path),
+            change,
+            missing_under
+        );
+    }
+}
+
+fn load_ignore_rules(db: &Connection, run_id: i64) -> Result<Vec<IgnoreRule>, sqlite::Error> {
+    let mut rules = Vec::new();
+    let mut statement =
+        db.prepare("SELECT source, pattern FROM run_ignore_rule WHERE run_id = ? ORDER BY id;")?;
+    statement.bind(1, run_id)?;
+    while let State::Row = statement.next()? {
+        rules.push(IgnoreRule {
+            source: statement.read::<String>(0)?,
+            pattern: statement.read::<String>(1)?,
+        });
+    }
+    Ok(rules)
+}
+
+fn load_run_errors(db: &Connection, run_id: i64) -> Result<Vec<FileError>, sqlite::Error> {
+    let mut errors = Vec::new();
+    let mut statement = db.prepare(
+        "SELECT file_name, phase, errno, message FROM file_error WHERE run_id = ? \
+            ORDER BY file_name;",
+    )?;
+    statement.bind(1, run_id)?;
+    while let State::Row = statement.next()? {
+        errors.push(FileError {
+            file_name: statement.read::<Vec<u8>>(0)?,
+            phase: statement.read::<String>(1)?,
+            errno: statement.read::<Option<i64>>(2)?,
+            message: statement.read::<String>(3)?,
+            interrupted: false,
+        });
+    }
+    Ok(errors)
+}
+
+// Unreadable files go first and loudest: on a disk that is going bad, an EIO is often the first
+// sign of it
+fn print_errors(errors: &[FileError]) {
+    if errors.is_empty() {
+        return;
+    }
+    outln!("UNREADABLE: {}", errors.len());
+    for error in errors {
+        outln!(
+            "UNREADABLE ({}): {}: {}",
+            error.phase,
+            display_file_name(&error.file_name),
+            error.message
+        );
+    }
+}
+
+// How many file_entry rows go into each transaction unless `check --batch-size` says otherwise;
+// committing per file makes SQLite sync to disk once per file
+const WRITE_BATCH_SIZE: usize = 1000;
+
+// How often `check` tells `frzr status` how far along it is
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// The longest `check` holds on to hashed files before committing them, however few there are
+const COMMIT_INTERVAL: Duration = Duration::from_secs(5);
+
+// A worker's verdict on one file, ready to be written as a file_entry
+struct HashedFile {
+    file_name: Vec<u8>,
+    entry: FileEntry,
+    // Whether the hash came from the previous run instead of reading the file
+    reused: bool,
+    // What the previous finished run has for the file, if anything; when this says the same,
+    // the file isn't written again
+    previous: Option<FileEntry>,
+    // Only when the run takes chunk digests and the file was read
+    chunks: Option<Chunks>,
+}
+
+// What the walk looks up about each path before handing it to a worker, on a connection of its
+// own: whether the run being resumed already did it, and the previous finished run's entry
+struct WalkLookup {
+    db: Connection,
+    run_id: i64,
+    resumed: bool,
+    previous_run_id: Option<i64>,
+}
+
+// The walk's thread, which hands the walker back when it's done, unless a lookup failed
+type Walk = JoinHandle<Result<Walker, sqlite::Error>>;
+
+// Hashes whatever the walker turns up with `jobs` worker threads, skipping files the resumed
+// run already did, and returns the channel their results come out of (in no particular order),
+// along with the walk's own errors. The channel closes once every file has been dealt with. The
+// walker is handed back once it is done, for the ignore rules and mounts it came across. With
+// quick, files that look untouched keep the previous run's hashes
+fn hash_files(
+    mut walker: Walker,
+    lookup: WalkLookup,
+    quick: bool,
+    jobs: usize,
+    limits: Arc<Limits>,
+    symlinks: SymlinkPolicy,
+    hashes: Arc<RunHashes>,
+) -> (Receiver<Result<HashedFile, FileError>>, Walk) {
+    // Bounded, so that the walk doesn't get far ahead of the workers
+    let (work_sender, work_receiver) = mpsc::sync_channel::<(PathBuf, Option<FileEntry>)>(jobs * 4);
+    let work_receiver = Arc::new(Mutex::new(work_receiver));
+    let (result_sender, result_receiver) = mpsc::channel();
+    let walk_result_sender = result_sender.clone();
+    let walk = thread::spawn(move || -> Result<Walker, sqlite::Error> {
+        let db = lookup.db;
+        let mut seen_statement =
+            db.prepare("SELECT 1 FROM path WHERE name = ? AND last_seen_run = ?;")?;
+        let mut previous = match lookup.previous_run_id {
+            Some(previous_run_id) => Some(EntryLookup::new(&db, previous_run_id)?),
+            None => None,
+        };
+        for walked in walker.by_ref() {
+            let filename = match walked {
+                Ok(filename) => filename,
+                Err(error) => {
+                    if walk_result_sender.send(Err(error)).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let file_name = filename.as_os_str().as_bytes();
+            if lookup.resumed {
+                seen_statement.reset()?;
+                seen_statement.bind(1, file_name)?;
+                seen_statement.bind(2, lookup.run_id)?;
+                let already_done = seen_statement.next()? == State::Row;
+                seen_statement.reset()?;
+                if already_done {
+                    continue;
+                }
+            }
+            let previous_entry = match &mut previous {
+                Some(previous) => previous.get(file_name)?,
+                None => None,
+            };
+            if stop_requested().is_some() || work_sender.send((filename, previous_entry)).is_err() {
+                break;
+            }
+        }
+        Ok(walker)
+    });
+    for _ in 0..jobs {
+        let work_receiver = Arc::clone(&work_receiver);
+        let result_sender = result_sender.clone();
+        let limits = Arc::clone(&limits);
+        let hashes = Arc::clone(&hashes);
+        thread::spawn(move || loop {
+            // The lock is only held while waiting for the next file, not while hashing it
+            let next = work_receiver.lock().unwrap().recv();
+            let (filename, previous) = match next {
+                Ok(work) if stop_requested().is_none() => work,
+                _ => break, // No more work, or we were told to stop
+            };
+            let result = hash_one_file(&filename, previous, quick, &limits, symlinks, &hashes);
+            if result_sender.send(result).is_err() {
+                break;
+            }
+        });
+    }
+    (result_receiver, walk)
+}
+
+fn hash_one_file(
+    filename: &PathBuf,
+    previous: Option<FileEntry>,
+    quick: bool,
+    limits: &Limits,
+    symlinks: SymlinkPolicy,
+    hashes: &RunHashes,
+) -> Result<HashedFile, FileError> {
+    limits.before_file();
+    // Stat before reading, so that a file edited while it is being hashed looks edited rather
+    // than corrupted. Only when links are being recorded do we look at the link itself
+    let metadata = match symlinks {
+        SymlinkPolicy::Record => fs::symlink_metadata(filename),
+        SymlinkPolicy::Follow | SymlinkPolicy::Skip => fs::metadata(filename),
+    };
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(e) => return Err(FileError::new(filename, "stat", &e)),
+    };
+    let vitals = Vitals::from_metadata(&metadata);
+    let file_name = filename.as_os_str().as_bytes().to_vec();
+    // The walker only hands over directories when they were swapped in after it looked
+    let entry_type = match EntryType::from_file_type(&metadata.file_type()) {
+        Some(entry_type) => entry_type,
+        None => {
+            let e = io::Error::other("not a file any more");
+            return Err(FileError::new(filename, "stat", &e));
+        }
+    };
+    if entry_type != EntryType::File {
+        // A link's content is where it points. FIFOs, sockets and devices are never opened;
+        // their metadata is all there is to record
+        let link_target = match entry_type {
+            EntryType::Symlink => match fs::read_link(filename) {
+                Ok(link_target) => Some(link_target.into_os_string().into_vec()),
+                Err(e) => return Err(FileError::new(filename, "read", &e)),
+            },
+            _ => None,
+        };
+        return Ok(HashedFile {
+            file_name,
+            entry: FileEntry {
+                entry_type,
+                file_hash: String::new(),
+                hash_algorithm: hashes.primary,
+                extra_digests: Vec::new(),
+                vitals: Some(vitals),
+                link_target,
+                chunk_size: None,
+            },
+            reused: false,
+            previous,
+            chunks: None,
+        });
+    }
+    // In quick mode the previous entry is reused whole, as long as it has every digest this run
+    // is taking; with nothing new about it, it isn't written again either
+    let reusable = previous
+        .as_ref()
+        .filter(|_| quick)
+        .filter(|entry| entry.entry_type == EntryType::File)
+        .filter(|entry| match &entry.vitals {
+            Some(previous_vitals) => previous_vitals.is_unchanged(&vitals),
+            None => false,
+        })
+        .filter(|entry| {
+            std::iter::once(&hashes.primary)
+                .chain(&hashes.extras)
+                .all(|algorithm| entry.digest(*algorithm).is_some())
+        });
+    if let Some(entry) = reusable {
+        let entry = FileEntry {
+            vitals: Some(vitals),
+            ..entry.clone()
+        };
+        return Ok(HashedFile {
+            file_name,
+            entry,
+            reused: true,
+            previous,
+            chunks: None,
+        });
+    }
+    let digests = compute_the_hash(filename, limits, hashes)
+        .map_err(|(phase, e)| FileError::new(filename, phase, &e))?;
+    Ok(HashedFile {
+        file_name,
+        entry: FileEntry {
+            entry_type,
+            file_hash: digests.file_hash,
+            hash_algorithm: hashes.primary,
+            extra_digests: digests.extra_digests,
+            vitals: Some(vitals),
+            link_target: None,
+            chunk_size: digests.chunks.as_ref().map(|chunks| chunks.chunk_size),
+        },
+        reused: false,
+        previous,
+        chunks: digests.chunks,
+    })
+}
+
+// Writes `check`'s results a batch, and a transaction, at a time (see WRITE_BATCH_SIZE). The
+// statements are prepared once, for the whole run
+struct EntryWriter<'db> {
+    db: &'db Connection,
+    run_id: i64,
+    seen_statement: Statement<'db>,
+    path_statement: Statement<'db>,
+    statement: Statement<'db>,
+    last_id: Statement<'db>,
+    digest_statement: Statement<'db>,
+    chunks_statement: Statement<'db>,
+    // For --benchmark
+    transactions: usize,
+    write_time: Duration,
+}
+
+impl<'db> EntryWriter<'db> {
+    fn new(db: &'db Connection, run_id: i64) -> Result<EntryWriter<'db>, sqlite::Error> {
+        Ok(EntryWriter {
+            db,
+            run_id,
+            seen_statement: db.prepare(
+                "INSERT INTO path (name, last_seen_run) VALUES (?1, ?2) \
+                    ON CONFLICT (name) DO UPDATE SET last_seen_run = ?2;",
+            )?,
+            path_statement: db.prepare("SELECT id FROM path WHERE name = ?;")?,
+            statement: db.prepare(format!(
+                "\
+                INSERT INTO file_entry \
+                    (run_id, path_id, algorithm, digest, link_target, file_type, {}) \
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);\
+                ",
+                VITALS_COLUMNS
+            ))?,
+            last_id: db.prepare("SELECT last_insert_rowid();")?,
+            digest_statement: db.prepare(
+                "INSERT INTO file_digest (file_entry_id, algorithm, digest) VALUES (?, ?, ?);",
+            )?,
+            chunks_statement: db.prepare(
+                "INSERT INTO file_chunks (file_entry_id, chunk_size, algorithm, digests) \
+                    VALUES (?, ?, ?, ?);",
+            )?,
+            transactions: 0,
+            write_time: Duration::ZERO,
+        })
+    }
+
+    fn write(&mut self, batch: &[HashedFile]) -> Result<(), sqlite::Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        self.db.execute("BEGIN;")?;
+        for hashed_file in batch {
+            if let Err(e) = self.write_one(hashed_file) {
+                let _ = self.db.execute("ROLLBACK;");
+                return Err(e);
+            }
+        }
+        self.db.execute("COMMIT;")?;
+        self.transactions += 1;
+        self.write_time += started.elapsed();
+        Ok(())
+    }
+
+    fn write_one(&mut self, hashed_file: &HashedFile) -> Result<(), sqlite::Error> {
+        self.seen_statement.reset()?;
+        self.seen_statement.bind(1, &hashed_file.file_name[..])?;
+        self.seen_statement.bind(2, self.run_id)?;
+        self.seen_statement.next()?;
+        // Nothing new to say about it; the entry from before stands for this run too
+        let unchanged = hashed_file
+            .previous
+            .as_ref()
+            .is_some_and(|entry| entry.same_record(&hashed_file.entry));
+        if unchanged {
+            return Ok(());
+        }
+        self.path_statement.reset()?;
+        self.path_statement.bind(1, &hashed_file.file_name[..])?;
+        self.path_statement.next()?;
+        let path_id = self.path_statement.read::<i64>(0)?;
+        let entry = &hashed_file.entry;
+        let statement = &mut self.statement;
+        statement.reset()?;
+        statement.bind(1, self.run_id)?;
+        statement.bind(2, path_id)?;
+        statement.bind(3, entry.hash_algorithm.id())?;
+        // Only files have a digest
+        let digest = Some(digest_to_bytes(&entry.file_hash)).filter(|digest| !digest.is_empty());
+        statement.bind(4, digest.as_deref())?;
+        statement.bind(5, entry.link_target.as_deref())?;
+        statement.bind(6, entry.entry_type.as_str())?;
+        if let Some(vitals) = &entry.vitals {
+            vitals.bind(statement, 7)?;
+        }
+        statement.next()?;
+        if entry.extra_digests.is_empty() && hashed_file.chunks.is_none() {
+            return Ok(());
+        }
+        self.last_id.reset()?;
+        self.last_id.next()?;
+        let file_entry_id = self.last_id.read::<i64>(0)?;
+        for (algorithm, digest) in &entry.extra_digests {
+            let statement = &mut self.digest_statement;
+            statement.reset()?;
+            statement.bind(1, file_entry_id)?;
+            statement.bind(2, algorithm.id())?;
+            statement.bind(3, &digest_to_bytes(digest)[..])?;
+            statement.next()?;
+        }
+        if let Some(chunks) = &hashed_file.chunks {
+            let statement = &mut self.chunks_statement;
+            statement.reset()?;
+            statement.bind(1, file_entry_id)?;
+            statement.bind(2, chunks.chunk_size as i64)?;
+            statement.bind(3, CHUNK_ALGORITHM.as_str())?;
+            statement.bind(4, &chunks.digests[..])?;
+            statement.next()?;
+        }
+        Ok(())
+    }
+}
+
+// What --benchmark prints: how fast this invocation got through its files, and how much of
+// that went on writing them out
+fn print_benchmark(
+    elapsed: Duration,
+    progress: &Progress,
+    writer: &EntryWriter,
+    batch_size: usize,
+) {
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    let files = progress.files_done - progress.files_before;
+    let mib = progress.bytes_hashed as f64 / (1024.0 * 1024.0);
+    outln!(
+        "Benchmark: {} files in {:.2}s ({:.1} files/s); read {:.1} MiB ({:.1} MiB/s)",
+        files,
+        seconds,
+        files as f64 / seconds,
+        mib,
+        mib / seconds
+    );
+    outln!(
+        "Benchmark: {} transactions of up to {} files took {:.2}s to write",
+        writer.transactions,
+        batch_size,
+        writer.write_time.as_secs_f64()
+    );
+}
+
+// The primary digest, the extras and the chunk digests, all from one read of the file. On
+// failure, says whether it was opening ("open") or reading ("read") the file that failed
+fn compute_the_hash(
+    file: &PathBuf,
+    limits: &Limits,
+    hashes: &RunHashes,
+) -> Result<Digests, (&'static str, io::Error)> {
+    // Something could have swapped a FIFO in since the stat; O_NONBLOCK keeps the open from
+    // waiting for a writer, and the fstat catches it before we try to read
+    let mut the_file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(file)
+        .map_err(|e| ("open", e))?;
+    let is_file = the_file
+        .metadata()
+        .map_err(|e| ("open", e))?
+        .file_type()
+        .is_file();
+    if !is_file {
+        let e = io::Error::other("not a regular file any more");
+        return Err(("open", e));
+    }
+
+    let mut hasher = hashes.primary.hasher();
+    let mut extra_hashers: Vec<_> = hashes
+        .extras
+        .iter()
+        .map(|algorithm| (*algorithm, algorithm.hasher()))
+        .collect();
+    let mut chunk_hasher = hashes.chunk_size.map(ChunkHasher::new);
+
+    // Read 128k at a time; big enough that the syscalls don't dominate
+    let mut buf = vec![0; 128 * 1024];
+    loop {
+        // Big files take a while; don't make a signal wait for them
+        if stop_requested().is_some() {
+            let e = io::Error::new(io::ErrorKind::Interrupted, "stop requested");
+            return Err(("read", e));
+        }
+        let num_bytes_read = the_file.read(&mut buf).map_err(|e| ("read", e))?;
+        if num_bytes_read == 0 {
+            break;
+        }
+        // read bytes from the file, pass them to the hashers:
+        hasher.update(&buf[..num_bytes_read]);
+        for (_, extra_hasher) in &mut extra_hashers {
+            extra_hasher.update(&buf[..num_bytes_read]);
+        }
+        if let Some(chunk_hasher) = &mut chunk_hasher {
+            chunk_hasher.update(&buf[..num_bytes_read]);
+        }
+        limits.after_read(num_bytes_read);
+    }
+
+    let extra_digests = extra_hashers
+        .into_iter()
+        .map(|(algorithm, extra_hasher)| (algorithm, extra_hasher.finish()))
+        .collect();
+    Ok(Digests {
+        file_hash: hasher.finish(),
+        extra_digests,
+        chunks: chunk_hasher.map(ChunkHasher::finish),
+    })
 }
@@ -0,0 +1,83 @@
+// Which filesystem a directory lives on. A run notes the device of its root and of every mount
+// point it comes across, so that a disk that was swapped, or didn't get mounted, can be told
+// apart from thousands of files going missing
+
+use std::fs;
+
+// One directory whose device differs from its parent's (or the root of the walk)
+#[derive(Clone, PartialEq)]
+pub struct Mount {
+    pub path: Vec<u8>,
+    pub device: u64,
+    // From /proc/self/mountinfo; empty when it can't be found there
+    pub fs_type: String,
+    pub source: String,
+}
+
+impl Mount {
+    pub fn new(path: Vec<u8>, device: u64) -> Mount {
+        let (fs_type, source) = describe_device(device).unwrap_or_default();
+        Mount {
+            path,
+            device,
+            fs_type,
+            source,
+        }
+    }
+
+    // Like "8:17 (vfat /dev/sdb1)"
+    pub fn describe(&self) -> String {
+        let (major, minor) = major_minor(self.device);
+        if self.fs_type.is_empty() {
+            format!("{}:{}", major, minor)
+        } else {
+            format!("{}:{} ({} {})", major, minor, self.fs_type, self.source)
+        }
+    }
+}
+
+// The glibc encoding of dev_t
+fn major_minor(device: u64) -> (u64, u64) {
+    let major = ((device >> 8) & 0xfff) | ((device >> 32) & 0xfffff000);
+    let minor = (device & 0xff) | ((device >> 12) & 0xffffff00);
+    (major, minor)
+}
+
+// (filesystem type, source) of whatever is mounted as device. Only Linux has mountinfo
+fn describe_device(device: u64) -> Option<(String, String)> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    let (major, minor) = major_minor(device);
+    let wanted = format!("{}:{}", major, minor);
+    // id parent major:minor root mount-point options [optional fields...] - type source super
+    for line in mountinfo.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.get(2) != Some(&wanted.as_str()) {
+            continue;
+        }
+        let separator = fields.iter().position(|field| *field == "-")?;
+        let fs_type = fields.get(separator + 1)?;
+        let source = fields.get(separator + 2)?;
+        return Some((fs_type.to_string(), source.to_string()));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn major_minor_splits_dev_t_like_glibc() {
+        for (major, minor) in [
+            (0, 0),
+            (8, 1),
+            (259, 3),
+            (4095, 255),
+            (4096, 256),
+            (0xfffff, 0xfffff),
+        ] {
+            let device = libc::makedev(major, minor);
+            assert_eq!(major_minor(device), (major as u64, minor as u64));
+        }
+    }
+}
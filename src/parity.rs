@@ -0,0 +1,361 @@
+// Reed-Solomon recovery data, so that damage `check` finds can be undone instead of only
+// reported. A protected file is cut into blocks of block_size bytes, and every STRIPE_BLOCKS
+// blocks in a row make a stripe with parity blocks of its own. A stripe with p parity blocks
+// survives any p of its blocks, data or parity, going bad. The digest of every block is kept,
+// so the bad ones are known rather than hunted for, and each parity block can stand in for
+// one of them. The parity file holds each stripe's parity blocks, one stripe after another
+
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use crate::hashing::{chunk_digest, ChunkHasher, Chunks, Hasher};
+use crate::stop_requested;
+
+// Blocks a stripe is made from when `protect` isn't given --block-size and `init` didn't set a
+// chunk size
+pub const DEFAULT_BLOCK_SIZE: u64 = 1024 * 1024;
+
+// Data blocks per stripe; only the last stripe of a file can have fewer. Every block of a stripe
+// has to be in memory to encode or repair it
+const STRIPE_BLOCKS: u64 = 32;
+
+// The biggest block --block-size allows. A stripe at 100% parity is twice STRIPE_BLOCKS of them,
+// which at this size comes to 256MiB in memory
+pub const MAX_BLOCK_SIZE: u64 = 4 * 1024 * 1024;
+
+// Parses --block-size like any other size, but no bigger than MAX_BLOCK_SIZE
+pub fn parse_block_size(size: &str) -> Result<u64, String> {
+    match crate::throttle::parse_byte_size(size)? {
+        block_size if block_size > MAX_BLOCK_SIZE => Err(format!(
+            "`{}` is bigger than a block can be, which is {}MiB",
+            size,
+            MAX_BLOCK_SIZE >> 20
+        )),
+        block_size => Ok(block_size),
+    }
+}
+
+// How a file of file_size bytes is cut up
+#[derive(Clone, Copy)]
+pub struct Layout {
+    pub file_size: u64,
+    pub block_size: u64,
+    // Parity blocks per stripe, as a percentage of its data blocks, rounded up
+    pub percent: u64,
+}
+
+// The blocks of one stripe, by index in the file and in the parity file
+struct Stripe {
+    first_block: u64,
+    data_blocks: u64,
+    first_parity: u64,
+    parity_blocks: u64,
+}
+
+impl Layout {
+    pub fn blocks(&self) -> u64 {
+        self.file_size.div_ceil(self.block_size)
+    }
+
+    pub fn parity_blocks(&self) -> u64 {
+        self.stripes()
+            .iter()
+            .map(|stripe| stripe.parity_blocks)
+            .sum()
+    }
+
+    fn stripes(&self) -> Vec<Stripe> {
+        let mut stripes = Vec::new();
+        let mut first_parity = 0;
+        let mut first_block = 0;
+        while first_block < self.blocks() {
+            let data_blocks = STRIPE_BLOCKS.min(self.blocks() - first_block);
+            let parity_blocks = (data_blocks * self.percent).div_ceil(100);
+            stripes.push(Stripe {
+                first_block,
+                data_blocks,
+                first_parity,
+                parity_blocks,
+            });
+            first_block += data_blocks;
+            first_parity += parity_blocks;
+        }
+        stripes
+    }
+
+    // The last block is whatever is left over
+    fn block_len(&self, index: u64) -> usize {
+        self.block_size
+            .min(self.file_size - index * self.block_size) as usize
+    }
+}
+
+// What `protect` took from its one read of a file
+pub struct Protected {
+    pub layout: Layout,
+    // Taken with whatever hasher it was given, to check against the baseline
+    pub file_hash: String,
+    pub block_digests: Chunks,
+    pub parity_digests: Chunks,
+}
+
+// Reads file once, writing its parity to parity_file and hashing it with hasher along the way.
+// The file has to stay the same size while it is read
+pub fn protect(
+    file: &Path,
+    block_size: u64,
+    percent: u64,
+    mut hasher: Hasher,
+    parity_file: &Path,
+) -> Result<Protected, io::Error> {
+    // Same as `check`: don't wait on a FIFO that was swapped in
+    let mut the_file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(file)?;
+    let metadata = the_file.metadata()?;
+    if !metadata.is_file() {
+        return Err(io::Error::other("not a regular file any more"));
+    }
+    // A file smaller than a block doesn't need parity any bigger than itself
+    let layout = Layout {
+        file_size: metadata.len(),
+        block_size: block_size.min(metadata.len()).max(1),
+        percent,
+    };
+    let mut parity = io::BufWriter::new(fs::File::create(parity_file)?);
+    let block_size = layout.block_size;
+    let mut block_hasher = ChunkHasher::new(block_size);
+    let mut parity_hasher = ChunkHasher::new(block_size);
+    for stripe in layout.stripes() {
+        if stop_requested().is_some() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "stop requested"));
+        }
+        let mut shards = Vec::with_capacity((stripe.data_blocks + stripe.parity_blocks) as usize);
+        for index in stripe.first_block..stripe.first_block + stripe.data_blocks {
+            let mut shard = vec![0; block_size as usize];
+            let len = layout.block_len(index);
+            the_file.read_exact(&mut shard[..len]).map_err(changed)?;
+            hasher.update(&shard[..len]);
+            block_hasher.update(&shard[..len]);
+            shards.push(shard);
+        }
+        for _ in 0..stripe.parity_blocks {
+            shards.push(vec![0; block_size as usize]);
+        }
+        codec(&stripe)?.encode(&mut shards).map_err(rs_error)?;
+        for shard in &shards[stripe.data_blocks as usize..] {
+            parity.write_all(shard)?;
+            parity_hasher.update(shard);
+        }
+    }
+    if the_file.read(&mut [0; 1])? != 0 {
+        return Err(changed(io::ErrorKind::UnexpectedEof.into()));
+    }
+    parity
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    Ok(Protected {
+        layout,
+        file_hash: hasher.finish(),
+        block_digests: block_hasher.finish(),
+        parity_digests: parity_hasher.finish(),
+    })
+}
+
+// What `repair` did, by block index
+pub struct Repaired {
+    pub repaired: Vec<u64>,
+    // Bad blocks in stripes with more bad blocks than parity blocks
+    pub unrepairable: Vec<u64>,
+}
+
+// Finds the blocks of file that don't match their digests and rebuilds them from the rest of
+// their stripe, in place. Nothing but a block that was already bad is written over, and only
+// with data that matches the block's digest. Once every block is good, the file is cut back to
+// its protected size, and its mtime is put back to what it was
+pub fn repair(
+    file: &Path,
+    layout: &Layout,
+    block_digests: &Chunks,
+    parity_digests: &Chunks,
+    parity_file: &Path,
+) -> Result<Repaired, io::Error> {
+    let the_file = fs::OpenOptions::new().read(true).write(true).open(file)?;
+    let metadata = the_file.metadata()?;
+    let parity = fs::File::open(parity_file)?;
+    let block_size = layout.block_size as usize;
+    let mut outcome = Repaired {
+        repaired: Vec::new(),
+        unrepairable: Vec::new(),
+    };
+    for stripe in layout.stripes() {
+        if stop_requested().is_some() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "stop requested"));
+        }
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::new();
+        let mut damaged = Vec::new();
+        for index in stripe.first_block..stripe.first_block + stripe.data_blocks {
+            let len = layout.block_len(index);
+            let block = read_block(&the_file, index * layout.block_size, len)?;
+            if block.len() == len && block_digests.digest(index) == Some(&chunk_digest(&block)) {
+                shards.push(Some(padded(block, block_size)));
+            } else {
+                shards.push(None);
+                damaged.push(index);
+            }
+        }
+        if damaged.is_empty() {
+            continue;
+        }
+        let mut bad_parity = 0;
+        for index in stripe.first_parity..stripe.first_parity + stripe.parity_blocks {
+            let block = read_block(&parity, index * layout.block_size, block_size)?;
+            if block.len() == block_size
+                && parity_digests.digest(index) == Some(&chunk_digest(&block))
+            {
+                shards.push(Some(block));
+            } else {
+                shards.push(None);
+                bad_parity += 1;
+            }
+        }
+        if damaged.len() as u64 + bad_parity > stripe.parity_blocks {
+            outcome.unrepairable.extend(damaged);
+            continue;
+        }
+        codec(&stripe)?
+            .reconstruct_data(&mut shards)
+            .map_err(rs_error)?;
+        for index in damaged {
+            let len = layout.block_len(index);
+            let shard = shards[(index - stripe.first_block) as usize]
+                .as_ref()
+                .unwrap();
+            if block_digests.digest(index) != Some(&chunk_digest(&shard[..len])) {
+                outcome.unrepairable.push(index);
+                continue;
+            }
+            the_file.write_all_at(&shard[..len], index * layout.block_size)?;
+            outcome.repaired.push(index);
+        }
+    }
+    if outcome.unrepairable.is_empty() && metadata.len() != layout.file_size {
+        the_file.set_len(layout.file_size)?;
+    }
+    if !outcome.repaired.is_empty() || metadata.len() != layout.file_size {
+        the_file.sync_all()?;
+        the_file.set_modified(metadata.modified()?)?;
+    }
+    Ok(outcome)
+}
+
+// Up to len bytes from offset; fewer if the file ends first
+fn read_block(file: &fs::File, offset: u64, len: usize) -> Result<Vec<u8>, io::Error> {
+    let mut block = vec![0; len];
+    let mut filled = 0;
+    while filled < len {
+        match file.read_at(&mut block[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    block.truncate(filled);
+    Ok(block)
+}
+
+// Every shard of a stripe has to be the same size, so the short last block is made up with
+// zeros, the same on the way in and out
+fn padded(mut block: Vec<u8>, block_size: usize) -> Vec<u8> {
+    block.resize(block_size, 0);
+    block
+}
+
+fn codec(stripe: &Stripe) -> Result<ReedSolomon, io::Error> {
+    ReedSolomon::new(stripe.data_blocks as usize, stripe.parity_blocks as usize).map_err(rs_error)
+}
+
+fn rs_error(e: reed_solomon_erasure::Error) -> io::Error {
+    io::Error::other(format!("Reed-Solomon: {:?}", e))
+}
+
+fn changed(e: io::Error) -> io::Error {
+    if e.kind() != io::ErrorKind::UnexpectedEof {
+        return e;
+    }
+    io::Error::other("the file changed size while it was being read")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(file_size: u64, percent: u64) -> Layout {
+        Layout {
+            file_size,
+            block_size: 4,
+            percent,
+        }
+    }
+
+    // (first_block, data_blocks, first_parity, parity_blocks) of every stripe
+    fn stripes(layout: &Layout) -> Vec<(u64, u64, u64, u64)> {
+        layout
+            .stripes()
+            .iter()
+            .map(|s| {
+                (
+                    s.first_block,
+                    s.data_blocks,
+                    s.first_parity,
+                    s.parity_blocks,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_empty_file_has_no_stripes() {
+        assert_eq!(stripes(&layout(0, 10)), vec![]);
+        assert_eq!(layout(0, 10).parity_blocks(), 0);
+    }
+
+    #[test]
+    fn only_the_last_stripe_is_short() {
+        // 32 full blocks fill one stripe exactly
+        assert_eq!(stripes(&layout(32 * 4, 10)), vec![(0, 32, 0, 4)]);
+        // One byte more is a block, and a stripe, of its own
+        let one_more = layout(32 * 4 + 1, 10);
+        assert_eq!(one_more.blocks(), 33);
+        assert_eq!(stripes(&one_more), vec![(0, 32, 0, 4), (32, 1, 4, 1)]);
+        assert_eq!(one_more.parity_blocks(), 5);
+        assert_eq!(one_more.block_len(31), 4);
+        assert_eq!(one_more.block_len(32), 1);
+    }
+
+    #[test]
+    fn parity_blocks_round_up() {
+        // 3.2 parity blocks is 4, and 0.1 is 1
+        assert_eq!(stripes(&layout(32 * 4, 10))[0].3, 4);
+        assert_eq!(stripes(&layout(4, 10))[0].3, 1);
+        assert_eq!(stripes(&layout(32 * 4, 100))[0].3, 32);
+        assert_eq!(
+            stripes(&layout(70 * 4, 50)),
+            vec![(0, 32, 0, 16), (32, 32, 16, 16), (64, 6, 32, 3)]
+        );
+    }
+
+    #[test]
+    fn block_size_is_capped() {
+        assert_eq!(parse_block_size("4MiB"), Ok(MAX_BLOCK_SIZE));
+        assert!(parse_block_size("4194305").is_err());
+    }
+}
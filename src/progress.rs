@@ -0,0 +1,210 @@
+// How far along a running `check` is, for `frzr status` to read from another process. `check`
+// writes it out every so often to .frzr/progress, by renaming a new copy over the old one so
+// that a reader never sees half of it, and removes it once every file is done. The total a run
+// will come to isn't known until its walk is over, so the previous finished run stands in for
+// it
+
+use std::fs;
+use std::io;
+use std::time::SystemTime;
+
+const PROGRESS_PATH: &str = "./.frzr/progress";
+
+pub struct Progress {
+    pub run_id: i64,
+    pub pid: u32,
+    // When this process took the run over, and when it last wrote this; seconds since the epoch
+    pub started: u64,
+    pub updated: u64,
+    // Everything the run has got through so far, including what it did before it was resumed.
+    // Bytes are the sizes of the files, whether or not they had to be read
+    pub files_done: u64,
+    pub bytes_done: u64,
+    // How much of that was already done when this process took over
+    pub files_before: u64,
+    pub bytes_before: u64,
+    // What this process actually read
+    pub bytes_hashed: u64,
+    pub errors: u64,
+    // From the previous finished run; None on the first run
+    pub files_expected: Option<u64>,
+    pub bytes_expected: Option<u64>,
+}
+
+impl Progress {
+    pub fn publish(&mut self) -> Result<(), io::Error> {
+        self.updated = now();
+        let optional = |value: Option<u64>| value.map_or("-".to_string(), |v| v.to_string());
+        let contents = format!(
+            "run {}\npid {}\nstarted {}\nupdated {}\nfiles_done {}\nbytes_done {}\n\
+             files_before {}\nbytes_before {}\nbytes_hashed {}\nerrors {}\n\
+             files_expected {}\nbytes_expected {}\n",
+            self.run_id,
+            self.pid,
+            self.started,
+            self.updated,
+            self.files_done,
+            self.bytes_done,
+            self.files_before,
+            self.bytes_before,
+            self.bytes_hashed,
+            self.errors,
+            optional(self.files_expected),
+            optional(self.bytes_expected),
+        );
+        let new_path = format!("{}.new", PROGRESS_PATH);
+        fs::write(&new_path, contents)?;
+        fs::rename(&new_path, PROGRESS_PATH)
+    }
+
+    // Whatever the last `check` to publish left; None if there is nothing, or nothing readable
+    pub fn read() -> Result<Option<Progress>, io::Error> {
+        let contents = match fs::read_to_string(PROGRESS_PATH) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Progress::parse(&contents))
+    }
+
+    fn parse(contents: &str) -> Option<Progress> {
+        let mut lines = contents.lines();
+        let mut field = |name: &str| -> Option<String> {
+            let value = lines.next()?.strip_prefix(name)?.strip_prefix(' ')?;
+            Some(value.to_string())
+        };
+        Some(Progress {
+            run_id: field("run")?.parse().ok()?,
+            pid: field("pid")?.parse().ok()?,
+            started: field("started")?.parse().ok()?,
+            updated: field("updated")?.parse().ok()?,
+            files_done: field("files_done")?.parse().ok()?,
+            bytes_done: field("bytes_done")?.parse().ok()?,
+            files_before: field("files_before")?.parse().ok()?,
+            bytes_before: field("bytes_before")?.parse().ok()?,
+            bytes_hashed: field("bytes_hashed")?.parse().ok()?,
+            errors: field("errors")?.parse().ok()?,
+            files_expected: field("files_expected")?.parse().ok(),
+            bytes_expected: field("bytes_expected")?.parse().ok(),
+        })
+    }
+
+    // Once every file is done there is nothing left to report progress on
+    pub fn clear() -> Result<(), io::Error> {
+        match fs::remove_file(PROGRESS_PATH) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // How long the rest should take at the rate this process has been going, in seconds. Going
+    // by files underestimates a run with big files still to come, and going by bytes one with
+    // lots of small files still to come, so it is whichever is longer
+    pub fn eta(&self) -> Option<u64> {
+        let elapsed = self.updated.saturating_sub(self.started);
+        let remaining = |done: u64, before: u64, expected: Option<u64>| {
+            let done_now = done.saturating_sub(before);
+            if done_now == 0 {
+                return None;
+            }
+            let to_go = expected?.saturating_sub(done);
+            Some((elapsed * to_go).div_ceil(done_now))
+        };
+        let by_files = remaining(self.files_done, self.files_before, self.files_expected);
+        let by_bytes = remaining(self.bytes_done, self.bytes_before, self.bytes_expected);
+        by_files.max(by_bytes)
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(started: u64, updated: u64) -> Progress {
+        Progress {
+            run_id: 2,
+            pid: 1,
+            started,
+            updated,
+            files_done: 0,
+            bytes_done: 0,
+            files_before: 0,
+            bytes_before: 0,
+            bytes_hashed: 0,
+            errors: 0,
+            files_expected: Some(100),
+            bytes_expected: Some(1000),
+        }
+    }
+
+    #[test]
+    fn parses_what_publish_writes() {
+        let parsed = Progress::parse(
+            "run 7\npid 42\nstarted 100\nupdated 160\nfiles_done 5\nbytes_done 500\n\
+             files_before 1\nbytes_before 100\nbytes_hashed 300\nerrors 2\n\
+             files_expected 10\nbytes_expected -\n",
+        )
+        .unwrap();
+        assert_eq!((parsed.run_id, parsed.pid), (7, 42));
+        assert_eq!((parsed.started, parsed.updated), (100, 160));
+        assert_eq!((parsed.files_done, parsed.bytes_done), (5, 500));
+        assert_eq!((parsed.files_before, parsed.bytes_before), (1, 100));
+        assert_eq!((parsed.bytes_hashed, parsed.errors), (300, 2));
+        assert_eq!(
+            (parsed.files_expected, parsed.bytes_expected),
+            (Some(10), None)
+        );
+    }
+
+    #[test]
+    fn refuses_anything_half_written_or_out_of_order() {
+        assert!(Progress::parse("").is_none());
+        assert!(Progress::parse("run 7\npid 42\nstarted 100\n").is_none());
+        assert!(Progress::parse("pid 42\nrun 7\n").is_none());
+        assert!(Progress::parse("run seven\n").is_none());
+    }
+
+    #[test]
+    fn no_eta_before_anything_is_done_or_without_a_previous_run() {
+        let mut p = progress(100, 100);
+        assert_eq!(p.eta(), None);
+        p.files_before = 10;
+        p.files_done = 10;
+        p.updated = 160;
+        assert_eq!(p.eta(), None);
+        p.files_done = 20;
+        p.files_expected = None;
+        p.bytes_expected = None;
+        assert_eq!(p.eta(), None);
+    }
+
+    #[test]
+    fn eta_goes_by_whichever_has_further_to_go() {
+        let mut p = progress(100, 160);
+        // Half the files in a minute, but only a tenth of the bytes
+        p.files_done = 50;
+        p.bytes_done = 100;
+        assert_eq!(p.eta(), Some(540));
+        p.bytes_done = 900;
+        assert_eq!(p.eta(), Some(60));
+    }
+
+    #[test]
+    fn eta_only_counts_what_this_process_did() {
+        let mut p = progress(100, 160);
+        p.files_before = 40;
+        p.files_done = 70;
+        p.bytes_expected = None;
+        // 30 files a minute, 30 to go
+        assert_eq!(p.eta(), Some(60));
+        // A run that found more than last time has nothing left it knows of
+        p.files_done = 120;
+        assert_eq!(p.eta(), Some(0));
+    }
+}
@@ -0,0 +1,545 @@
+// The frzr DB and how its schema got to where it is. MIGRATIONS is every change ever made to
+// the schema, oldest first; a DB at schema version N has had the first N applied, and is
+// brought up to date by applying the rest in order, each in a transaction of its own. Once a
+// migration has shipped it never changes, since DBs out there have already been through it;
+// anything new goes on the end
+
+use std::fmt;
+use std::path::Path;
+
+use sqlite::Connection;
+use sqlite::State;
+
+use crate::hashing::digest_to_bytes;
+
+pub const DB_PATH: &str = "./.frzr/frzr.db";
+
+const MIGRATIONS: &[Migration] = &[
+    // 1: Run may grow to include other statistics about the run, like number of files processed.
+    //
+    // I'm trying the filename as BLOB instead of string because, at least for Linux,
+    // not all valid paths are strings in any single encoding
+    // file_name in this case means "relative path to the file, including the filename"
+    //
+    // Instead of using time, datetime, or date for file_entry, I think I should do run_id
+    // A run should be inserted at the top of any checksum calculation, should have a start
+    // and an end, and then can be referenced from file_entry. This might be useful
+    //
+    // Should file_hash be a blob, also? Probably easier to to select on if it is a string
+    // That raises the question for file_name, too. Not sure about types here
+    Migration::Sql(
+        "
+        CREATE TABLE IF NOT EXISTS run (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            start_time datetime NOT NULL,
+            end_time datetime
+            );
+        CREATE TABLE IF NOT EXISTS file_entry (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            file_name BLOB,
+            file_hash STRING,
+            run_id INTEGER NOT NULL,
+            FOREIGN KEY(run_id) REFERENCES run(id)
+            );
+        ",
+    ),
+    // 2: baseline holds the hash each file is trusted to have, one row per file. A file that
+    // is accepted as missing simply has no row. resolution is the append-only log of every
+    // acceptance, so the baseline's history can always be reconstructed
+    Migration::Sql(
+        "
+        CREATE TABLE IF NOT EXISTS baseline (
+            file_name BLOB PRIMARY KEY NOT NULL,
+            file_hash STRING,
+            file_entry_id INTEGER NOT NULL,
+            accepted_by STRING,
+            accepted_at datetime NOT NULL,
+            FOREIGN KEY(file_entry_id) REFERENCES file_entry(id)
+            );
+        CREATE TABLE IF NOT EXISTS resolution (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            file_name BLOB NOT NULL,
+            old_hash STRING,
+            new_hash STRING,
+            run_id INTEGER NOT NULL,
+            accepted_by STRING,
+            accepted_at datetime NOT NULL,
+            FOREIGN KEY(run_id) REFERENCES run(id)
+            );
+        ",
+    ),
+    // 3: Vitals from stat(2), so that a hash change can be judged against what the filesystem
+    // says happened to the file. Entries recorded before this have them all NULL
+    Migration::Sql(
+        "
+        ALTER TABLE file_entry ADD COLUMN size INTEGER;
+        ALTER TABLE file_entry ADD COLUMN mtime INTEGER;
+        ALTER TABLE file_entry ADD COLUMN mtime_nsec INTEGER;
+        ALTER TABLE file_entry ADD COLUMN ctime INTEGER;
+        ALTER TABLE file_entry ADD COLUMN ctime_nsec INTEGER;
+        ALTER TABLE file_entry ADD COLUMN inode INTEGER;
+        ALTER TABLE file_entry ADD COLUMN device INTEGER;
+        ALTER TABLE file_entry ADD COLUMN mode INTEGER;
+        ",
+    ),
+    // 4: Whether a run reread every file ('full') or trusted unchanged vitals ('quick'). Older
+    // runs were all full
+    Migration::Sql(
+        "
+        ALTER TABLE run ADD COLUMN mode STRING NOT NULL DEFAULT 'full';
+        ",
+    ),
+    // 5: 'running' until the run either finishes or is stopped by a signal, in which case it
+    // becomes 'aborted' with the reason alongside. A run that is 'running' with no `check`
+    // process around was killed outright. Runs from before this can only be told apart by
+    // their end_time
+    Migration::Sql(
+        "
+        ALTER TABLE run ADD COLUMN status STRING NOT NULL DEFAULT 'running';
+        ALTER TABLE run ADD COLUMN abort_reason STRING;
+        UPDATE run SET status = 'finished' WHERE end_time IS NOT NULL;
+        ",
+    ),
+    // 6: Files and directories a run couldn't read, and at which step: "stat", "open" or
+    // "read". errno is NULL when the error didn't come from the OS
+    Migration::Sql(
+        "
+        CREATE TABLE IF NOT EXISTS file_error (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            run_id INTEGER NOT NULL,
+            file_name BLOB NOT NULL,
+            phase STRING NOT NULL,
+            errno INTEGER,
+            message STRING NOT NULL,
+            FOREIGN KEY(run_id) REFERENCES run(id)
+            );
+        ",
+    ),
+    // 7: Every ignore pattern a run was walked with, and the .frzrignore it came from (or
+    // "command line" for --exclude/--include), so a file that silently dropped out of
+    // coverage can be traced to the rule responsible
+    Migration::Sql(
+        "
+        CREATE TABLE IF NOT EXISTS run_ignore_rule (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            run_id INTEGER NOT NULL,
+            source STRING NOT NULL,
+            pattern STRING NOT NULL,
+            FOREIGN KEY(run_id) REFERENCES run(id)
+            );
+        ",
+    ),
+    // 8: With --symlinks=record, a link is stored with where it points (and an empty hash)
+    // instead of being hashed as its target. Each run notes which policy it walked with;
+    // runs before this followed links to files and didn't go into linked directories
+    Migration::Sql(
+        "
+        ALTER TABLE file_entry ADD COLUMN link_target BLOB;
+        ALTER TABLE run ADD COLUMN symlinks STRING;
+        ",
+    ),
+    // 9: What kind of thing each entry is: 'file', 'symlink', 'fifo', 'socket', 'char' or
+    // 'block'. Only files have a hash; devices are told apart by rdev. Runs before this
+    // hung on FIFOs, so anything they finished recording is a file or a link
+    Migration::Sql(
+        "
+        ALTER TABLE file_entry ADD COLUMN file_type STRING NOT NULL DEFAULT 'file';
+        ALTER TABLE file_entry ADD COLUMN rdev INTEGER;
+        UPDATE file_entry SET file_type = 'symlink' WHERE link_target IS NOT NULL;
+        ",
+    ),
+    // 10: The device of a run's root and of every mount point it came across, with the
+    // filesystem type and source when /proc/self/mountinfo says. device is the raw dev_t
+    Migration::Sql(
+        "
+        CREATE TABLE IF NOT EXISTS run_mount (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            run_id INTEGER NOT NULL,
+            path BLOB NOT NULL,
+            device INTEGER NOT NULL,
+            fs_type STRING NOT NULL,
+            source STRING NOT NULL,
+            FOREIGN KEY(run_id) REFERENCES run(id)
+            );
+        ",
+    ),
+    // 11: Which algorithm each run's file_hash values were taken with; everything before this
+    // was SHA-256. Any other digests taken in the same read go in file_digest. config holds
+    // settings from `frzr init`, like the algorithm to hash with by default
+    Migration::Sql(
+        "
+        ALTER TABLE run ADD COLUMN hash_algorithm STRING NOT NULL DEFAULT 'sha256';
+        CREATE TABLE IF NOT EXISTS file_digest (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            file_entry_id INTEGER NOT NULL,
+            algorithm STRING NOT NULL,
+            digest STRING NOT NULL,
+            FOREIGN KEY(file_entry_id) REFERENCES file_entry(id)
+            );
+        CREATE TABLE IF NOT EXISTS config (
+            key STRING PRIMARY KEY NOT NULL,
+            value STRING NOT NULL
+            );
+        ",
+    ),
+    // 12: A digest of every chunk_size bytes of a file, so that a damaged file can be narrowed
+    // down to the ranges that changed. digests holds them all back to back, 8 bytes each,
+    // rather than a row per chunk; a big file has tens of thousands
+    Migration::Sql(
+        "
+        CREATE TABLE IF NOT EXISTS file_chunks (
+            file_entry_id INTEGER PRIMARY KEY NOT NULL,
+            chunk_size INTEGER NOT NULL,
+            algorithm STRING NOT NULL,
+            digests BLOB NOT NULL,
+            FOREIGN KEY(file_entry_id) REFERENCES file_entry(id)
+            );
+        ",
+    ),
+    // 13: Files `protect` wrote parity for, and the baseline entry they matched at the time.
+    // The parity itself is in .frzr/parity/<id>. block_digests and parity_digests are
+    // packed like file_chunks' digests, one per block of the file and of the parity file,
+    // so `repair` can tell which blocks went bad
+    Migration::Sql(
+        "
+        CREATE TABLE IF NOT EXISTS parity (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            file_name BLOB NOT NULL,
+            file_entry_id INTEGER NOT NULL,
+            file_size INTEGER NOT NULL,
+            block_size INTEGER NOT NULL,
+            percent INTEGER NOT NULL,
+            block_digests BLOB NOT NULL,
+            parity_digests BLOB NOT NULL,
+            created_at datetime NOT NULL,
+            FOREIGN KEY(file_entry_id) REFERENCES file_entry(id)
+            );
+        ",
+    ),
+    // 14: Paths are stored once, in path, and everything else refers to them by id. Digests
+    // are stored as bytes, with the algorithm as its HashAlgorithm id, rather than as hex
+    // and a name. A run only writes a file_entry for a path when something about it changed
+    // since the last finished run, and a 'gone' one when it disappeared; what a run saw of a
+    // path is its newest entry from that run or any finished run before it. last_seen_run
+    // is the latest run that found the path, so that an interrupted run knows what it already
+    // did. Runs from before this keep a row for every file, and get 'gone' rows in place of
+    // the files they didn't have, which comes to the same thing. Resolutions keep their
+    // paths and hex digests as they were, since they are a log for reading
+    Migration::Code(normalize_storage),
+    // 15: `report` and `resolve` look up whether each changed path was ever resolved, one path
+    // at a time
+    Migration::Sql(
+        "
+        CREATE INDEX IF NOT EXISTS resolution_file_name ON resolution (file_name);
+        ",
+    ),
+];
+
+// Either SQL to run as-is, or for anything SQL alone can't do, a function
+enum Migration {
+    Sql(&'static str),
+    Code(fn(&Connection) -> Result<(), sqlite::Error>),
+}
+
+// The schema version this build of frzr reads and writes
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+#[derive(Debug)]
+pub enum DbError {
+    // Nothing at DB_PATH; `init` hasn't been run here
+    NotInitialized,
+    // Written by a newer frzr, which may have changed things this one doesn't know about
+    TooNew(i64),
+    // Written by an older frzr, and opened for reading, which can't upgrade it
+    Outdated,
+    // Taking the copy of the DB that goes before an upgrade
+    Backup(sqlite::Error),
+    Sqlite(sqlite::Error),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::NotInitialized => {
+                write!(f, "there is no {}; run `frzr init` first", DB_PATH)
+            }
+            DbError::TooNew(version) => write!(
+                f,
+                "the DB is at schema version {}, but this frzr only knows up to {}; use a newer \
+                 frzr",
+                version, SCHEMA_VERSION
+            ),
+            DbError::Outdated => write!(
+                f,
+                "the DB is from an older frzr, and has to be upgraded before it can be read"
+            ),
+            DbError::Backup(e) => write!(f, "couldn't back up the DB before upgrading it: {:?}", e),
+            DbError::Sqlite(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl From<sqlite::Error> for DbError {
+    fn from(e: sqlite::Error) -> DbError {
+        DbError::Sqlite(e)
+    }
+}
+
+// Creates the DB for `init`
+pub fn create_db() -> Result<Connection, DbError> {
+    let connection = sqlite::open(DB_PATH)?;
+    configure(&connection, Access::Write)?;
+    migrate(&connection)?;
+    Ok(connection)
+}
+
+// What a verb is going to do with the DB
+#[derive(Clone, Copy)]
+pub enum Access {
+    // Nothing is written, so not even an upgrade, and an outdated DB is refused
+    Read,
+    // The caller holds the lock in lock.rs, so it's safe to upgrade the DB
+    Write,
+}
+
+pub fn initialized() -> bool {
+    Path::new(DB_PATH).exists()
+}
+
+// Opens the DB that `init` made, upgrading it for Write if it was written by an older frzr.
+// Never creates one, so that a verb run in the wrong directory says so instead of starting an
+// empty DB there
+pub fn open_db(access: Access) -> Result<Connection, DbError> {
+    if !initialized() {
+        return Err(DbError::NotInitialized);
+    }
+    let flags = sqlite::OpenFlags::new().set_read_write();
+    let connection = Connection::open_with_flags(DB_PATH, flags)?;
+    configure(&connection, access)?;
+    match access {
+        Access::Read => match current_version(&connection)? {
+            version if version > SCHEMA_VERSION => return Err(DbError::TooNew(version)),
+            version if version < SCHEMA_VERSION => return Err(DbError::Outdated),
+            _ => (),
+        },
+        Access::Write => migrate(&connection)?,
+    }
+    Ok(connection)
+}
+
+// The schema version without creating schema_version if it isn't there, which a reader can't
+fn current_version(connection: &Connection) -> Result<i64, sqlite::Error> {
+    let mut statement = connection.prepare(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'schema_version';",
+    )?;
+    statement.next()?;
+    if statement.read::<i64>(0)? == 0 {
+        return Ok(0);
+    }
+    schema_version(connection)
+}
+
+// With a write-ahead log, a commit is an append to the log instead of a rewrite of the pages it
+// touched, and needs only one sync. synchronous=FULL makes that sync part of every commit, so a
+// power cut takes nothing that was committed with it: a transaction is either all there or not
+// there at all, and a finished run stays finished. Readers don't need the lock a writer takes,
+// but can still run into SQLite's own for a moment, so they wait instead of failing
+// Switching the journal mode is a write, so only a writer does it: the DB it creates or upgrades
+// is in WAL mode from then on, and a reader finds it that way. The busy timeout goes first, so
+// that the switch waits for another process too
+fn configure(connection: &Connection, access: Access) -> Result<(), sqlite::Error> {
+    connection.execute("PRAGMA busy_timeout = 5000;")?;
+    if let Access::Write = access {
+        connection.execute("PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL;")?;
+    }
+    Ok(())
+}
+
+fn schema_version(connection: &Connection) -> Result<i64, sqlite::Error> {
+    let mut statement =
+        connection.prepare("SELECT version FROM schema_version ORDER BY version DESC LIMIT 1;")?;
+    match statement.next()? {
+        State::Row => statement.read::<i64>(0),
+        State::Done => Ok(0),
+    }
+}
+
+// Applies whatever migrations the DB hasn't had. An existing DB is copied first, so that an
+// upgrade that goes wrong, or a downgrade of frzr, still has the old DB to go back to
+fn migrate(connection: &Connection) -> Result<(), DbError> {
+    connection.execute(
+        "
+        CREATE TABLE IF NOT EXISTS schema_version (id INTEGER PRIMARY KEY ASC, version INTEGER);
+        ",
+    )?;
+    let version = schema_version(connection)?;
+    if version > SCHEMA_VERSION {
+        return Err(DbError::TooNew(version));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    if version > 0 {
+        let backup = format!("{}.v{}.bak", DB_PATH, version);
+        // One left by an upgrade that failed part way is from the same version, and just as good
+        if !Path::new(&backup).exists() {
+            let mut statement = connection.prepare("VACUUM INTO ?;")?;
+            statement.bind(1, backup.as_str())?;
+            statement.next().map_err(DbError::Backup)?;
+        }
+        eprintln!(
+            "Upgrading the DB from schema version {} to {}; the old one is at {}",
+            version, SCHEMA_VERSION, backup
+        );
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        // IMMEDIATE takes the write lock up front, so the version read next can't change under
+        // this transaction. Another process that got here first has already applied this one
+        connection.execute("BEGIN IMMEDIATE;")?;
+        let applied = schema_version(connection).and_then(|version| {
+            if version > index as i64 {
+                return Ok(version);
+            }
+            match migration {
+                Migration::Sql(sql) => connection.execute(sql)?,
+                Migration::Code(apply) => apply(connection)?,
+            }
+            let mut statement =
+                connection.prepare("INSERT INTO schema_version (version) VALUES (?);")?;
+            statement.bind(1, index as i64 + 1)?;
+            statement.next()?;
+            Ok(index as i64 + 1)
+        });
+        let applied = applied.and_then(|version| {
+            connection.execute("COMMIT;")?;
+            Ok(version)
+        });
+        match applied {
+            Ok(version) if version > SCHEMA_VERSION => return Err(DbError::TooNew(version)),
+            Ok(_) => (),
+            Err(e) => {
+                let _ = connection.execute("ROLLBACK;");
+                return Err(DbError::Sqlite(e));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Migration 14. The tables are rebuilt with the new columns, keeping every id, so nothing that
+// points at a file_entry has to change
+fn normalize_storage(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "
+        CREATE TABLE path (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            name BLOB UNIQUE NOT NULL,
+            last_seen_run INTEGER
+            );
+        CREATE INDEX file_entry_file_name ON file_entry (file_name);
+        INSERT OR IGNORE INTO path (name) SELECT file_name FROM file_entry ORDER BY id;
+        INSERT OR IGNORE INTO path (name) SELECT file_name FROM baseline;
+        UPDATE path SET last_seen_run =
+            (SELECT MAX(run_id) FROM file_entry WHERE file_name = path.name);
+
+        CREATE TABLE new_file_entry (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            path_id INTEGER NOT NULL,
+            run_id INTEGER NOT NULL,
+            file_type STRING NOT NULL DEFAULT 'file',
+            algorithm INTEGER,
+            digest BLOB,
+            link_target BLOB,
+            size INTEGER,
+            mtime INTEGER,
+            mtime_nsec INTEGER,
+            ctime INTEGER,
+            ctime_nsec INTEGER,
+            inode INTEGER,
+            device INTEGER,
+            mode INTEGER,
+            rdev INTEGER,
+            FOREIGN KEY(path_id) REFERENCES path(id),
+            FOREIGN KEY(run_id) REFERENCES run(id)
+            );
+        INSERT INTO new_file_entry (id, path_id, run_id, file_type, algorithm, digest,
+                link_target, size, mtime, mtime_nsec, ctime, ctime_nsec, inode, device, mode,
+                rdev)
+            SELECT file_entry.id, path.id, run_id, file_type,
+                CASE run.hash_algorithm WHEN 'sha256' THEN 1 WHEN 'sha512' THEN 2
+                    WHEN 'blake3' THEN 3 WHEN 'xxh3' THEN 4 WHEN 'crc32c' THEN 5 END,
+                NULLIF(file_hash, ''), link_target, size, mtime, mtime_nsec, ctime, ctime_nsec,
+                inode, device, file_entry.mode, rdev
+            FROM file_entry
+            JOIN path ON path.name = file_entry.file_name
+            JOIN run ON run.id = file_entry.run_id;
+        DROP TABLE file_entry;
+        ALTER TABLE new_file_entry RENAME TO file_entry;
+        CREATE INDEX file_entry_path_run ON file_entry (path_id, run_id);
+        CREATE INDEX file_entry_run ON file_entry (run_id);
+
+        INSERT INTO file_entry (path_id, run_id, file_type)
+            SELECT earlier.path_id, run.id, 'gone' FROM run
+            JOIN file_entry AS earlier ON earlier.run_id = (
+                SELECT MAX(id) FROM run AS before
+                    WHERE before.id < run.id AND before.status = 'finished')
+            WHERE run.status = 'finished' AND earlier.file_type != 'gone' AND NOT EXISTS (
+                SELECT 1 FROM file_entry AS now
+                    WHERE now.path_id = earlier.path_id AND now.run_id = run.id);
+
+        CREATE TABLE new_file_digest (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            file_entry_id INTEGER NOT NULL,
+            algorithm INTEGER NOT NULL,
+            digest BLOB NOT NULL,
+            FOREIGN KEY(file_entry_id) REFERENCES file_entry(id)
+            );
+        INSERT INTO new_file_digest (id, file_entry_id, algorithm, digest)
+            SELECT id, file_entry_id,
+                CASE algorithm WHEN 'sha256' THEN 1 WHEN 'sha512' THEN 2 WHEN 'blake3' THEN 3
+                    WHEN 'xxh3' THEN 4 WHEN 'crc32c' THEN 5 END,
+                digest
+            FROM file_digest;
+        DROP TABLE file_digest;
+        ALTER TABLE new_file_digest RENAME TO file_digest;
+        CREATE INDEX file_digest_file_entry ON file_digest (file_entry_id);
+
+        CREATE TABLE new_baseline (
+            path_id INTEGER PRIMARY KEY NOT NULL,
+            file_entry_id INTEGER NOT NULL,
+            accepted_by STRING,
+            accepted_at datetime NOT NULL,
+            FOREIGN KEY(path_id) REFERENCES path(id),
+            FOREIGN KEY(file_entry_id) REFERENCES file_entry(id)
+            );
+        INSERT INTO new_baseline (path_id, file_entry_id, accepted_by, accepted_at)
+            SELECT path.id, file_entry_id, accepted_by, accepted_at FROM baseline
+            JOIN path ON path.name = baseline.file_name;
+        DROP TABLE baseline;
+        ALTER TABLE new_baseline RENAME TO baseline;
+        CREATE INDEX baseline_file_entry ON baseline (file_entry_id);
+
+        CREATE INDEX file_error_run ON file_error (run_id);
+        ",
+    )?;
+    // Hex to bytes is the one thing older SQLite can't do itself
+    for (table, column) in [("file_entry", "digest"), ("file_digest", "digest")] {
+        let mut digests = Vec::new();
+        let mut statement = connection.prepare(format!(
+            "SELECT id, {} FROM {} WHERE {} IS NOT NULL;",
+            column, table, column
+        ))?;
+        while let State::Row = statement.next()? {
+            digests.push((statement.read::<i64>(0)?, statement.read::<String>(1)?));
+        }
+        let mut statement =
+            connection.prepare(format!("UPDATE {} SET {} = ? WHERE id = ?;", table, column))?;
+        for (id, digest) in digests {
+            statement.reset()?;
+            statement.bind(1, &digest_to_bytes(&digest)[..])?;
+            statement.bind(2, id)?;
+            statement.next()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+// Rate limiting for `check`, so that a background run doesn't make the machine it is on
+// unresponsive
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Paces some quantity (bytes read, files opened) to a fixed rate, shared by every worker thread.
+// Each caller reserves the next slot on a common timeline and sleeps until it comes around, so
+// the combined rate of all threads never exceeds `per_second`
+pub struct RateLimiter {
+    per_second: f64,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u64) -> RateLimiter {
+        RateLimiter {
+            per_second: per_second as f64,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Blocks until `amount` more units fit under the rate
+    pub fn take(&self, amount: u64) {
+        let now = Instant::now();
+        let start = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            // Time spent idle doesn't bank up into a burst later
+            let start = (*next_slot).max(now);
+            *next_slot = start + Duration::from_secs_f64(amount as f64 / self.per_second);
+            start
+        };
+        if start > now {
+            thread::sleep(start - now);
+        }
+    }
+}
+
+// Every limit `check` was asked to respect; None means unlimited
+#[derive(Default)]
+pub struct Limits {
+    pub read_rate: Option<RateLimiter>,
+    pub file_rate: Option<RateLimiter>,
+}
+
+impl Limits {
+    // Called once per file, before anything is done with it
+    pub fn before_file(&self) {
+        if let Some(file_rate) = &self.file_rate {
+            file_rate.take(1);
+        }
+    }
+
+    // Called with the number of bytes each read returned
+    pub fn after_read(&self, bytes: usize) {
+        if let Some(read_rate) = &self.read_rate {
+            read_rate.take(bytes as u64);
+        }
+    }
+}
+
+// Parses a size per second such as `50MiB/s`, `10M` or `512k` into bytes per second. Suffixes
+// are binary whether or not they have the `i`; the trailing `/s` is optional
+pub fn parse_byte_rate(rate: &str) -> Result<u64, String> {
+    let trimmed = rate.trim();
+    parse_byte_size(trimmed.strip_suffix("/s").unwrap_or(trimmed))
+}
+
+// Parses a size such as `1MiB`, `64k` or `4096` into bytes, the same way as parse_byte_rate
+pub fn parse_byte_size(size: &str) -> Result<u64, String> {
+    let trimmed = size.trim();
+    let split_at = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split_at);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("`{}` doesn't start with a number", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(format!("`{}` has an unknown unit", size)),
+    };
+    match number.checked_mul(multiplier) {
+        Some(0) => Err(String::from("it has to be more than zero")),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("`{}` is too large", size)),
+    }
+}
+
+// Puts this thread, and any threads it starts afterwards, at the lowest CPU priority and in the
+// idle I/O scheduling class, where it only gets the disk when nobody else wants it
+pub fn lower_priority() -> Result<(), std::io::Error> {
+    // On Linux niceness is per-thread, and new threads inherit it from the one creating them
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // From linux/ioprio.h, which libc doesn't expose
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_are_binary_with_or_without_the_i() {
+        assert_eq!(parse_byte_size("4096"), Ok(4096));
+        assert_eq!(parse_byte_size("4096B"), Ok(4096));
+        assert_eq!(parse_byte_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_byte_size("64KB"), Ok(64 << 10));
+        assert_eq!(parse_byte_size("1MiB"), Ok(1 << 20));
+        assert_eq!(parse_byte_size(" 3 gib "), Ok(3 << 30));
+        assert_eq!(parse_byte_size("2T"), Ok(2 << 40));
+    }
+
+    #[test]
+    fn rates_may_end_in_per_second() {
+        assert_eq!(parse_byte_rate("50MiB/s"), Ok(50 << 20));
+        assert_eq!(parse_byte_rate("10M"), Ok(10 << 20));
+        assert_eq!(parse_byte_rate("512k/s"), Ok(512 << 10));
+    }
+
+    #[test]
+    fn zero_is_refused() {
+        assert!(parse_byte_size("0").is_err());
+        assert!(parse_byte_rate("0MiB/s").is_err());
+    }
+
+    #[test]
+    fn overflow_is_refused() {
+        assert_eq!(parse_byte_size("16777215TiB"), Ok(16777215 << 40));
+        assert!(parse_byte_size("16777216TiB").is_err());
+        assert!(parse_byte_size("18446744073709551616").is_err());
+    }
+
+    #[test]
+    fn anything_else_is_refused() {
+        for size in ["", "MiB", "-1M", "1.5M", "10 parsecs", "10M/s/s"] {
+            assert!(parse_byte_rate(size).is_err(), "{}", size);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+// The walk of the tree under `check`'s root. Paths are handed out one at a time as the walk
+// gets to them, so that hashing can start straight away and memory only grows with the depth
+// of the tree (and the width of the directories along the way), not with the number of files.
+// Each directory's entries are visited in byte order of their names, so two walks of the same
+// tree always go the same way
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::frzrignore::{IgnoreStack, IGNORE_FILE_NAME};
+use crate::mounts::Mount;
+use crate::{stop_requested, FileError, SymlinkPolicy};
+
+// Yields every file to check, plus the directories and entries that couldn't be read. Ends
+// early if a signal asks us to stop
+pub(crate) struct Walker {
+    pub ignores: IgnoreStack,
+    // The root, and every directory on a different device than the one it is in
+    pub mounts: Vec<Mount>,
+    symlinks: SymlinkPolicy,
+    one_file_system: bool,
+    // Every directory between the root and where the walk is now, innermost last
+    levels: Vec<Level>,
+    // Errors waiting to be handed out, oldest first
+    errors: VecDeque<FileError>,
+}
+
+// One directory that the walk is in the middle of
+struct Level {
+    // (device, inode). Following a symlink back into a directory we are already inside would
+    // go around in circles forever
+    identity: (u64, u64),
+    // Whether the directory had a .frzrignore, which has to stop applying once we leave it
+    entered_ignores: bool,
+    // The entries not visited yet, with their type as read_dir saw it
+    entries: std::vec::IntoIter<(PathBuf, io::Result<fs::FileType>)>,
+}
+
+impl Walker {
+    pub(crate) fn new(
+        root: PathBuf,
+        ignores: IgnoreStack,
+        symlinks: SymlinkPolicy,
+        one_file_system: bool,
+    ) -> Walker {
+        let mut walker = Walker {
+            ignores,
+            mounts: Vec::new(),
+            symlinks,
+            one_file_system,
+            levels: Vec::new(),
+            errors: VecDeque::new(),
+        };
+        walker.descend(root);
+        walker
+    }
+
+    // Starts on a directory's entries. An unreadable directory or entry is recorded and
+    // skipped; the rest of the tree is still worth checking
+    fn descend(&mut self, path: PathBuf) {
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                self.errors.push_back(FileError::new(&path, "stat", &e));
+                return;
+            }
+        };
+        let identity = (metadata.dev(), metadata.ino());
+        if self.levels.iter().any(|level| level.identity == identity) {
+            let e = io::Error::from_raw_os_error(libc::ELOOP);
+            self.errors.push_back(FileError::new(&path, "stat", &e));
+            return;
+        }
+        let crosses_device = match self.levels.last() {
+            Some(parent) => parent.identity.0 != identity.0,
+            None => true,
+        };
+        if crosses_device {
+            let mount_path = path.as_os_str().as_bytes().to_vec();
+            self.mounts.push(Mount::new(mount_path, identity.0));
+            if self.one_file_system && !self.levels.is_empty() {
+                return;
+            }
+        }
+        let dir_iter = match fs::read_dir(&path) {
+            Ok(dir_iter) => dir_iter,
+            Err(e) => {
+                self.errors.push_back(FileError::new(&path, "open", &e));
+                return;
+            }
+        };
+        let mut entries = Vec::new();
+        for entry in dir_iter {
+            match entry {
+                Ok(entry) => entries.push((entry.path(), entry.file_type())),
+                Err(e) => {
+                    // Whatever came before the error is still worth checking
+                    self.errors.push_back(FileError::new(&path, "read", &e));
+                    break;
+                }
+            }
+        }
+        entries.sort_by(|(a, _), (b, _)| a.as_os_str().as_bytes().cmp(b.as_os_str().as_bytes()));
+        // Rules in this directory's .frzrignore apply to everything under it, until we leave
+        // it. A broken one is reported, and the directory is walked as if it weren't there
+        let entered_ignores = match self.ignores.enter(&path) {
+            Ok(entered) => entered,
+            Err(e) => {
+                let ignore_file = path.join(IGNORE_FILE_NAME);
+                self.errors
+                    .push_back(FileError::new(&ignore_file, "read", &e));
+                false
+            }
+        };
+        self.levels.push(Level {
+            identity,
+            entered_ignores,
+            entries: entries.into_iter(),
+        });
+    }
+
+    // Whether the walk should go into path, rather than hand it out; None if it shouldn't be
+    // looked at all
+    fn classify(&mut self, path: &Path, file_type: io::Result<fs::FileType>) -> Option<bool> {
+        let file_type = match file_type {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                self.errors.push_back(FileError::new(path, "stat", &e));
+                return None;
+            }
+        };
+        if !file_type.is_symlink() {
+            return Some(file_type.is_dir());
+        }
+        match self.symlinks {
+            SymlinkPolicy::Skip => None,
+            // The link itself is what gets recorded, wherever it points
+            SymlinkPolicy::Record => Some(false),
+            SymlinkPolicy::Follow => match fs::metadata(path) {
+                Ok(metadata) => Some(metadata.is_dir()),
+                Err(e) => {
+                    // Dangling
+                    self.errors.push_back(FileError::new(path, "stat", &e));
+                    None
+                }
+            },
+        }
+    }
+}
+
+impl Iterator for Walker {
+    type Item = Result<PathBuf, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(error) = self.errors.pop_front() {
+                return Some(Err(error));
+            }
+            if stop_requested().is_some() {
+                return None;
+            }
+            let (path, file_type) = match self.levels.last_mut()?.entries.next() {
+                Some(entry) => entry,
+                None => {
+                    // Done with this directory
+                    let level = self.levels.pop().unwrap();
+                    if level.entered_ignores {
+                        self.ignores.leave();
+                    }
+                    continue;
+                }
+            };
+            let is_dir = match self.classify(&path, file_type) {
+                Some(is_dir) => is_dir,
+                None => continue,
+            };
+            // Our own DB is never worth checking
+            if path == Path::new("./.frzr") || self.ignores.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                self.descend(path);
+                continue;
+            }
+            return Some(Ok(path));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Helpers the integration tests share: running the frzr binary in a tree of files made for the
+// test, and somewhere to put that tree
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub fn frzr(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_frzr"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+// The exit code, with whatever it printed if the code isn't the one expected
+pub fn assert_exit(output: &Output, code: i32) {
+    assert_eq!(
+        output.status.code(),
+        Some(code),
+        "stdout: {}\nstderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+// An empty directory of its own for each test, left behind only if the test fails
+pub fn scratch_dir(name: &str) -> PathBuf {
+    static MADE: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "frzr-{}-{}-{}",
+        name,
+        std::process::id(),
+        MADE.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Permissions don't stop root, so a test that needs something to be unreadable runs frzr as
+// nobody when it is root, in a tree nobody owns
+pub const NOBODY: u32 = 65534;
+
+pub fn frzr_unprivileged(dir: &Path, args: &[&str]) -> Output {
+    if !is_root() {
+        return frzr(dir, args);
+    }
+    // The build's own copy can be somewhere nobody can get to, like under /root
+    let bin_dir = scratch_dir("bin");
+    let bin = bin_dir.join("frzr");
+    fs::copy(env!("CARGO_BIN_EXE_frzr"), &bin).unwrap();
+    fs::set_permissions(&bin_dir, fs::Permissions::from_mode(0o755)).unwrap();
+    let output = Command::new(&bin)
+        .args(args)
+        .current_dir(dir)
+        .uid(NOBODY)
+        .gid(NOBODY)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&bin_dir).unwrap();
+    output
+}
+
+pub fn give_to_nobody(path: &Path) {
+    if !is_root() {
+        return;
+    }
+    std::os::unix::fs::chown(path, Some(NOBODY), Some(NOBODY)).unwrap();
+    if path.is_dir() {
+        for entry in fs::read_dir(path).unwrap() {
+            give_to_nobody(&entry.unwrap().path());
+        }
+    }
+}
+
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
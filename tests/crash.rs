@@ -0,0 +1,188 @@
+// Kills `frzr check` at random points and makes sure the DB comes through it: SQLite finds no
+// damage, every run that had finished is exactly as it was, the killed run is either finished
+// or plainly not, `frzr fsck` is happy, and `check --resume` ends up where a full run would.
+// The kill points come from a fixed seed, so that every run tries the same ones; FRZR_CRASH_SEED
+// picks others, to replay a failure, or is "random" for a new seed from the clock
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use sqlite::{Connection, State};
+
+mod common;
+use common::frzr;
+
+const KILLS: usize = 12;
+
+const DEFAULT_SEED: u64 = 0x5eed_f122;
+
+// xorshift64; good enough to spread kill points around
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+// A few thousand small files, so that there are lots of batches to be in the middle of, and a
+// few big ones, so that there are long reads to be in the middle of
+fn make_tree(dir: &Path) {
+    for d in 0..20 {
+        let sub = dir.join(format!("d{}", d));
+        fs::create_dir_all(&sub).unwrap();
+        for f in 0..100 {
+            fs::write(sub.join(format!("f{}", f)), format!("{} {}\n", d, f)).unwrap();
+        }
+    }
+    for b in 0..3 {
+        let bytes: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i * (b + 7)) as u8).collect();
+        fs::write(dir.join(format!("big{}", b)), bytes).unwrap();
+    }
+}
+
+// Some edits, a deletion and an addition, so that every run has something to write
+fn mutate(dir: &Path, rng: &mut Rng, round: usize) {
+    for _ in 0..20 {
+        let file = dir.join(format!("d{}/f{}", rng.below(20), rng.below(100)));
+        fs::write(file, format!("round {} {}\n", round, rng.next())).unwrap();
+    }
+    let _ = fs::remove_file(dir.join(format!("d{}/f{}", rng.below(20), rng.below(100))));
+    fs::write(dir.join(format!("d0/new{}", round)), "new\n").unwrap();
+}
+
+// Every finished run, with every row it wrote
+fn finished_runs(db: &Connection) -> BTreeMap<i64, Vec<String>> {
+    let mut runs = BTreeMap::new();
+    let mut statement = db
+        .prepare("SELECT id, end_time FROM run WHERE status = 'finished';")
+        .unwrap();
+    while let State::Row = statement.next().unwrap() {
+        let id = statement.read::<i64>(0).unwrap();
+        let mut rows = vec![statement.read::<String>(1).unwrap()];
+        let mut entries = db
+            .prepare(
+                "SELECT id, path_id, file_type, hex(digest) FROM file_entry \
+                    WHERE run_id = ? ORDER BY id;",
+            )
+            .unwrap();
+        entries.bind(1, id).unwrap();
+        while let State::Row = entries.next().unwrap() {
+            rows.push(format!(
+                "{} {} {} {}",
+                entries.read::<i64>(0).unwrap(),
+                entries.read::<i64>(1).unwrap(),
+                entries.read::<String>(2).unwrap(),
+                entries
+                    .read::<Option<String>>(3)
+                    .unwrap()
+                    .unwrap_or_default()
+            ));
+        }
+        runs.insert(id, rows);
+    }
+    runs
+}
+
+fn assert_consistent(dir: &Path, db: &Connection, seed: u64) {
+    let mut statement = db.prepare("PRAGMA integrity_check;").unwrap();
+    statement.next().unwrap();
+    assert_eq!(statement.read::<String>(0).unwrap(), "ok", "seed {}", seed);
+    // The latest run either got all the way to the end, or is plainly unfinished
+    let mut statement = db
+        .prepare("SELECT status, end_time FROM run ORDER BY id DESC LIMIT 1;")
+        .unwrap();
+    statement.next().unwrap();
+    let status = statement.read::<String>(0).unwrap();
+    let end_time = statement.read::<Option<String>>(1).unwrap();
+    assert_eq!(status == "finished", end_time.is_some(), "seed {}", seed);
+    let fsck = frzr(dir, &["fsck"]);
+    assert!(
+        fsck.status.success(),
+        "seed {}: {}",
+        seed,
+        String::from_utf8_lossy(&fsck.stdout)
+    );
+}
+
+#[test]
+fn killed_checks_leave_the_db_consistent() {
+    let seed = match std::env::var("FRZR_CRASH_SEED") {
+        Ok(seed) if seed == "random" => {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64
+                | 1
+        }
+        // xorshift never gets anywhere from 0
+        Ok(seed) => seed.parse::<u64>().unwrap().max(1),
+        Err(_) => DEFAULT_SEED,
+    };
+    eprintln!("FRZR_CRASH_SEED={}", seed);
+    let mut rng = Rng(seed);
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("frzr-crash-{}-{}", std::process::id(), seed));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    make_tree(&dir);
+    assert!(frzr(&dir, &["init"]).status.success());
+    let started = Instant::now();
+    assert!(frzr(&dir, &["check"]).status.success());
+    // Kill points spread over a bit more than a whole run, so that some land after the end
+    let run_time = started.elapsed().as_millis() as u64 * 5 / 4 + 1;
+
+    for round in 0..KILLS {
+        let mut args = vec!["check", "--batch-size", "25"];
+        // A resumed run keeps what it recorded before it was killed, so changing files in between
+        // would make it differ from a fresh run by design; only new runs get changes to find
+        if rng.below(2) == 0 {
+            args.push("--resume");
+        } else {
+            mutate(&dir, &mut rng, round);
+        }
+        let before = finished_runs(&Connection::open(dir.join(".frzr/frzr.db")).unwrap());
+        let mut child = Command::new(env!("CARGO_BIN_EXE_frzr"))
+            .args(&args)
+            .current_dir(&dir)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(rng.below(run_time)));
+        // SIGKILL, so nothing gets the chance to tidy up
+        let _ = child.kill();
+        child.wait().unwrap();
+
+        let db = Connection::open(dir.join(".frzr/frzr.db")).unwrap();
+        let after = finished_runs(&db);
+        for (id, rows) in &before {
+            assert_eq!(after.get(id), Some(rows), "seed {}: run {}", seed, id);
+        }
+        assert_consistent(&dir, &db, seed);
+    }
+
+    // Whatever state the last kill left, a resume finishes it the same way a fresh run would
+    let resumed = frzr(&dir, &["check", "--resume"]);
+    assert!(resumed.status.code() != Some(2), "seed {}", seed);
+    let resumed_dump = frzr(&dir, &["dump"]);
+    let fresh = frzr(&dir, &["check"]);
+    assert!(fresh.status.code() != Some(2), "seed {}", seed);
+    let fresh_dump = frzr(&dir, &["dump"]);
+    assert!(resumed_dump.status.success() && fresh_dump.status.success());
+    assert_eq!(resumed_dump.stdout, fresh_dump.stdout, "seed {}", seed);
+    let db = Connection::open(dir.join(".frzr/frzr.db")).unwrap();
+    assert_consistent(&dir, &db, seed);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,75 @@
+// `protect` and `repair` end to end: damage is undone, damage beyond the parity is owned up to,
+// and parity taken before a change was accepted is never used to undo that change
+
+use std::fs;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+mod common;
+use common::{assert_exit, frzr, scratch_dir, stdout};
+
+// Enough 64KiB blocks to make a couple of stripes, with a short one at the end
+fn protected_tree(name: &str) -> (std::path::PathBuf, Vec<u8>) {
+    let dir = scratch_dir(name);
+    let data: Vec<u8> = (0..40 * 65536 + 1234u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    fs::write(dir.join("data"), &data).unwrap();
+    assert_exit(&frzr(&dir, &["init", "--chunk-size", "64KiB"]), 0);
+    assert_exit(&frzr(&dir, &["check"]), 0);
+    assert_exit(&frzr(&dir, &["protect", "data"]), 0);
+    (dir, data)
+}
+
+fn overwrite(file: &Path, offset: u64, bytes: &[u8]) {
+    let file = fs::OpenOptions::new().write(true).open(file).unwrap();
+    file.write_all_at(bytes, offset).unwrap();
+}
+
+#[test]
+fn repair_undoes_damage() {
+    let (dir, data) = protected_tree("repair");
+    overwrite(&dir.join("data"), 100, &[0xff; 10]);
+    overwrite(&dir.join("data"), 33 * 65536 + 5, &[0; 3]);
+    let repaired = frzr(&dir, &["repair", "data"]);
+    assert_exit(&repaired, 0);
+    assert!(stdout(&repaired).contains("repaired: ./data: 2 of 41 blocks"));
+    assert_eq!(fs::read(dir.join("data")).unwrap(), data);
+    assert_exit(&frzr(&dir, &["check"]), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn repair_owns_up_to_damage_beyond_the_parity() {
+    let (dir, _) = protected_tree("unrepairable");
+    // The default 10% gives the first stripe of 32 blocks 4 parity blocks
+    for block in 0..5 {
+        overwrite(&dir.join("data"), block * 65536, &[0xaa; 16]);
+    }
+    let damaged = fs::read(dir.join("data")).unwrap();
+    let repaired = frzr(&dir, &["repair", "data"]);
+    assert_exit(&repaired, 1);
+    assert!(stdout(&repaired).contains("COULD NOT REPAIR: ./data"));
+    assert_eq!(fs::read(dir.join("data")).unwrap(), damaged);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn repair_leaves_accepted_changes_alone() {
+    let (dir, _) = protected_tree("stale");
+    overwrite(&dir.join("data"), 100, b"0123456789");
+    assert_exit(&frzr(&dir, &["check"]), 1);
+    assert_exit(&frzr(&dir, &["resolve", "--accept-all"]), 0);
+    let accepted = fs::read(dir.join("data")).unwrap();
+    let repaired = frzr(&dir, &["repair", "data"]);
+    assert_exit(&repaired, 1);
+    assert!(stdout(&repaired).contains("STALE PARITY: ./data"));
+    assert_eq!(fs::read(dir.join("data")).unwrap(), accepted);
+    assert_exit(&frzr(&dir, &["check"]), 0);
+    // Protecting it again makes the parity good for the new contents
+    assert_exit(&frzr(&dir, &["protect", "data"]), 0);
+    overwrite(&dir.join("data"), 200, &[0; 4]);
+    assert_exit(&frzr(&dir, &["repair", "data"]), 0);
+    assert_eq!(fs::read(dir.join("data")).unwrap(), accepted);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,46 @@
+// A directory that can't be read hides what's in it, but that doesn't make any of it missing:
+// `check`, `report` and `status` say it's unreadable, no 'gone' rows are written for what's
+// under it, and so `resolve` has nothing to drop from the baseline
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+mod common;
+use common::{assert_exit, frzr_unprivileged, give_to_nobody, scratch_dir, stdout};
+
+#[test]
+fn files_under_an_unreadable_directory_are_not_missing() {
+    let dir = scratch_dir("unreadable");
+    fs::create_dir_all(dir.join("d/e")).unwrap();
+    fs::write(dir.join("d/x"), "x\n").unwrap();
+    fs::write(dir.join("d/e/y"), "y\n").unwrap();
+    fs::write(dir.join("other"), "other\n").unwrap();
+    give_to_nobody(&dir);
+    assert_exit(&frzr_unprivileged(&dir, &["init"]), 0);
+    assert_exit(&frzr_unprivileged(&dir, &["check"]), 0);
+
+    fs::set_permissions(dir.join("d"), fs::Permissions::from_mode(0o000)).unwrap();
+    let checked = frzr_unprivileged(&dir, &["check"]);
+    assert_exit(&checked, 2);
+    let checked = stdout(&checked);
+    assert!(checked.contains("UNREADABLE (open): ./d"), "{}", checked);
+    assert!(
+        !checked.contains("./d/x") && !checked.contains("./d/e/y"),
+        "{}",
+        checked
+    );
+    let reported = stdout(&frzr_unprivileged(&dir, &["report"]));
+    assert!(
+        !reported.contains("./d/x") && !reported.contains("./d/e/y"),
+        "{}",
+        reported
+    );
+    let status = stdout(&frzr_unprivileged(&dir, &["status"]));
+    assert!(status.contains("UNREADABLE (open): ./d"), "{}", status);
+    assert_exit(&frzr_unprivileged(&dir, &["resolve", "--accept-all"]), 0);
+
+    // Once it can be read again, everything in it is just as it was in the baseline
+    fs::set_permissions(dir.join("d"), fs::Permissions::from_mode(0o755)).unwrap();
+    assert_exit(&frzr_unprivileged(&dir, &["check"]), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,72 @@
+// A DB from an older frzr is upgraded by whichever verb opens it first, under the lock, so a
+// `check` and a `report` started together on one don't trip over each other
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use sqlite::Connection;
+
+mod common;
+use common::{assert_exit, frzr, scratch_dir, stdout};
+
+// What the very first frzr made: schema version 1, with one finished run
+fn make_v1_db(dir: &Path) {
+    let frzr_dir = dir.join(".frzr");
+    for entry in fs::read_dir(&frzr_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    let db = Connection::open(frzr_dir.join("frzr.db")).unwrap();
+    db.execute(
+        "
+        CREATE TABLE schema_version (id INTEGER PRIMARY KEY ASC, version INTEGER);
+        INSERT INTO schema_version (version) VALUES (1);
+        CREATE TABLE run (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            start_time datetime NOT NULL,
+            end_time datetime
+            );
+        CREATE TABLE file_entry (
+            id INTEGER PRIMARY KEY ASC NOT NULL,
+            file_name BLOB,
+            file_hash STRING,
+            run_id INTEGER NOT NULL,
+            FOREIGN KEY(run_id) REFERENCES run(id)
+            );
+        INSERT INTO run (start_time, end_time) VALUES (CURRENT_TIMESTAMP, CURRENT_TIMESTAMP);
+        ",
+    )
+    .unwrap();
+}
+
+#[test]
+fn an_old_db_is_upgraded_once_under_the_lock() {
+    let dir = scratch_dir("upgrade");
+    fs::write(dir.join("a"), "a\n").unwrap();
+    assert_exit(&frzr(&dir, &["init"]), 0);
+    for _ in 0..5 {
+        make_v1_db(&dir);
+        let check = Command::new(env!("CARGO_BIN_EXE_frzr"))
+            .arg("check")
+            .current_dir(&dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let report = frzr(&dir, &["report"]);
+        let check = check.wait_with_output().unwrap();
+        assert_ne!(check.status.code(), Some(2), "{}", stdout(&check));
+        assert_exit(&report, 0);
+        assert!(dir.join(".frzr/frzr.db.v1.bak").exists());
+    }
+    // A reader on its own upgrades it too
+    make_v1_db(&dir);
+    let report = frzr(&dir, &["report"]);
+    assert_exit(&report, 0);
+    assert!(!stdout(&report).contains("problem"));
+    assert_exit(&frzr(&dir, &["fsck"]), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}